@@ -1,30 +1,56 @@
-use crate::instruction::{Opcode, Operand, COPY_STATIC_HEADER};
-use crate::{parse_u8_literal, VecExt};
-use anyhow::anyhow;
-use regex::Regex;
-use std::collections::{HashMap, HashSet};
+use crate::assembler::debug_info::{DebugInfo, LineInfo};
+use crate::assembler::diagnostic::{Diagnostic, DiagnosticKind, Diagnostics};
+use crate::assembler::expr::{Position, Scope, Usage};
+use crate::assembler::include::{FileSystem, SourceLoader};
+use crate::assembler::macros::{Expansion, Macros};
+use crate::assembler::symbol::{SymbolKind, SymbolTable};
+use crate::emulator::NULL_INSTRUCTION;
+use crate::header::{Header, HeaderVersion};
+use crate::instruction::{Opcode, Operand, OperandSymbol};
+use std::collections::HashMap;
 use std::fmt;
-use std::hash::Hash;
+use std::ops::Range;
+use std::rc::Rc;
 use std::str::FromStr;
 use yeet_ops::yeet;
 
+mod data;
+pub mod debug_info;
+pub mod diagnostic;
+mod expr;
+pub mod include;
+mod listing;
+pub mod macros;
+mod pseudo;
+pub mod symbol;
+
 /// LEG-Architecture uses fixed-length instructions.
 pub const INST_LENGTH: u8 = 4;
 
 #[derive(Debug)]
 pub struct Assembler {
-    consts: HashMap<String, u8>,
-    labels: HashMap<String, u16>,
+    symbols: SymbolTable,
     sections: Sections,
-    /// The `copystatic` portion.
+    header: Header,
+    /// The encoded `header`, with the data.
     binary_header: Vec<u8>,
+    /// Errors found in sections other than `.code`.
+    diagnostics: Vec<Diagnostic>,
 }
 
 #[derive(Debug, Clone)]
 pub struct AssemblyTarget {
     pub commented_binary: String,
+    /// Addresses, bytes and source lines, followed by the symbol table and
+    /// the RAM usage.
+    pub listing: String,
     /// Target binary
     pub binary: BinaryParts,
+    /// Label name to program address mapping.
+    pub labels: HashMap<String, u16>,
+    /// All the constants, data symbols and labels.
+    pub symbols: SymbolTable,
+    pub debug_info: DebugInfo,
 }
 
 #[derive(Debug, Clone)]
@@ -44,147 +70,263 @@ impl BinaryParts {
 }
 
 impl Assembler {
-    pub fn new<S: AsRef<str>>(code: S) -> anyhow::Result<Self> {
-        let code = code.as_ref();
+    /// Errors are not returned here except for a missing `.code` section;
+    /// they are collected and reported by [`Assembler::assemble`], together with
+    /// the ones in the code, all at once.
+    pub fn new<S: AsRef<str>>(code: S) -> Result<Self, Diagnostics> {
+        Self::with_file_name(code, "<source>")
+    }
 
-        let mut consts: HashMap<String, u8> = HashMap::new();
-        let mut copy_static_info = (0_u8, 0_u8);
-        let mut copy_static_data: Option<Vec<u8>> = None;
-        let mut binary_header = Vec::new();
+    /// Same as [`Assembler::new`], but also records the source file name
+    /// in the debug info and diagnostics. Included files are read from the
+    /// file system, relative to `file_name`.
+    pub fn with_file_name<S: AsRef<str>>(
+        code: S,
+        file_name: impl Into<String>,
+    ) -> Result<Self, Diagnostics> {
+        Self::with_loader(code, file_name, &FileSystem)
+    }
 
-        let sections = Sections::new(code)?;
+    /// Same as [`Assembler::with_file_name`], but reads the included files
+    /// with `loader`.
+    pub fn with_loader<S: AsRef<str>>(
+        code: S,
+        file_name: impl Into<String>,
+        loader: &dyn SourceLoader,
+    ) -> Result<Self, Diagnostics> {
+        let code = code.as_ref();
+        let file_name = file_name.into();
+        let file = file_name.as_str();
+
+        let mut diagnostics = Vec::new();
+        let mut symbols = SymbolTable::default();
+        let mut segments = Vec::new();
+
+        let mut sections = include::load_sections(code, file, loader, &mut diagnostics);
+        let mut macros = Macros::new(&sections.macros, &mut diagnostics);
+        if let Some(s) = sections.find_mut("code") {
+            let lines = macros.expand(&s.body_lines, &mut diagnostics);
+            s.body_lines = pseudo::expand(&lines, &mut diagnostics);
+        }
 
         if let Some(s) = sections.find("consts") {
-            for x in &s.body_lines {
-                let x = Self::remove_comment(x);
+            for line in &s.body_lines {
+                let x = Self::remove_comment(line.text.trim());
                 if x.is_empty() {
                     continue;
                 }
-                let mut split = x.split(' ');
-                let name = split.next().ok_or(anyhow!(".consts: syntax error: {x}"))?;
-                let value = split.next().ok_or(anyhow!(".consts: syntax error: {x}"))?;
-                consts.insert(
-                    name.into(),
-                    parse_u8_literal(value).ok_or(anyhow!("Invalid u8 literal: {x}"))?,
-                );
-            }
-        }
-
-        let code_section = sections
-            .find("code")
-            .ok_or(anyhow!("Missing .code section"))?;
-        let mut labels = Self::read_labels(&code_section.body_lines);
-
-        // parse .data section
-        if let Some(s) = sections.find("data") {
-            let mut static_data = Vec::new();
-
-            let mem_start = s.args.first().ok_or(anyhow!(".data: missing mem_start"))?;
-            let mut mem_start =
-                parse_u8_literal(mem_start).ok_or(anyhow!("Invalid mem_start: {mem_start}"))?;
-            copy_static_info.1 = mem_start;
-            for line in &s.body_lines {
-                let line = Self::remove_comment(line);
-                let parts = regex!(r#"^(\S+) (.*?) (\S+)$"#)
-                    .capture_vec(line)
-                    .ok_or(anyhow!(".data: syntax error"))?;
-                let parts = &parts[1..];
-                if parts.len() != 2 && parts.len() != 3 {
-                    yeet!(anyhow!(".data: syntax error"));
+                let name = x.split_whitespace().next().unwrap();
+                let value = x[name.len()..].trim();
+                if value.is_empty() {
+                    diagnostics.push(line.error(
+                        x,
+                        DiagnosticKind::Syntax,
+                        ".consts: expected `<name> <value>`",
+                    ));
+                    continue;
                 }
-                let data_value =
-                    parse_data_value(parts[1]).ok_or(anyhow!(".data: parsing value error"))?;
-                let data_byte = match data_value {
-                    DataValue::String(s) => s,
-                    DataValue::Array(s) => s,
-                    DataValue::Byte(b) => {
-                        vec![b]
-                    }
+                let scope = Scope {
+                    symbols: &symbols,
+                    usage: Usage::Const,
+                    position: None,
                 };
-                data_byte.iter().for_each(|&x| static_data.push(x));
-                consts.insert(parts[0].into(), mem_start);
-                if let Some(&length_name) = parts.get(2)
-                    && length_name != "_"
-                {
-                    consts.insert(length_name.into(), data_byte.len().try_into()?);
-                }
-                mem_start += u8::try_from(data_byte.len())?;
+                let value = expr::evaluate_u8(value, &scope).unwrap_or_else(|e| {
+                    diagnostics.push(line.error(e.part, e.kind, e.message));
+                    // still define it, so its usages won't be reported again
+                    0
+                });
+                Self::define_symbol(
+                    &mut symbols,
+                    line,
+                    name,
+                    name,
+                    SymbolKind::Const,
+                    value as u16,
+                    &mut diagnostics,
+                );
             }
-            // here mem_start is the static data length
-            copy_static_info.0 = mem_start;
-            copy_static_data = Some(static_data);
         }
 
-        // correct labels
-        // add the initial 4-byte `copystatic` instruction along with its data
-        for x in labels.values_mut() {
-            *x += INST_LENGTH as u16;
-            *x += copy_static_data
-                .as_ref()
-                .map(|x| x.len())
-                .unwrap_or_default() as u16;
+        let Some(code_section) = sections.find("code") else {
+            diagnostics.push(Diagnostic::new(
+                DiagnosticKind::MissingSection,
+                file,
+                "Missing .code section",
+            ));
+            return Err(Diagnostics(diagnostics));
+        };
+        let labels = Self::read_labels(&code_section.body_lines, &mut diagnostics);
+
+        let mut deferred = Vec::new();
+        let mut titles = Vec::new();
+        for s in sections.find_all("data") {
+            let (segment, values) = data::layout(s, &mut symbols, &mut diagnostics);
+            segments.push(segment);
+            deferred.push(values);
+            titles.push(&s.title);
         }
+        data::check_overlaps(&segments, &titles, &mut diagnostics);
 
-        let entry_section = sections
-            .find("entry")
-            .ok_or(anyhow!("Missing .entry section"))?;
-        let entrypoint = entry_section
-            .args
-            .first()
-            .ok_or(anyhow!(".entry: missing entrypoint"))?;
-
-        let &entrypoint_addr = labels
-            .get(entrypoint)
-            .ok_or(anyhow!("Cannot find entrypoint: {entrypoint}"))?;
-        binary_header.push_all(
-            [
-                COPY_STATIC_HEADER,
-                copy_static_info.0,
-                copy_static_info.1,
-                entrypoint_addr
-                    .try_into()
-                    .map_err(|_| anyhow!("entrypoint does not support 16-bit address"))?,
-            ]
-            .into_iter(),
-        );
-        if let Some(x) = copy_static_data {
-            let mut data_string = String::new();
-            for &x in &x {
-                use fmt::Write;
-                write!(&mut data_string, "{} ", x).unwrap();
-            }
-            if data_string.len() > 0 {
-                data_string.remove(data_string.len() - 1);
+        let offsets = labels
+            .iter()
+            .rev()
+            .filter_map(|x| Some((x.name.as_deref()?, x.offset)))
+            .collect();
+        let entry_offset = Self::find_entrypoint(&sections, &offsets, file).unwrap_or_else(|e| {
+            diagnostics.push(e);
+            0
+        });
+        // version 1 where possible, which the game circuit loads
+        let v1_size = Header::size_of(HeaderVersion::V1, &segments) as u16;
+        let version = Header::required_version(entry_offset + v1_size, &segments);
+        let header_size = Header::size_of(version, &segments) as u16;
+
+        // the code follows the header along with its data
+        for x in &labels {
+            let address = x.offset + header_size;
+            match &x.name {
+                Some(name) => {
+                    Self::define_symbol(
+                        &mut symbols,
+                        x.line,
+                        x.label,
+                        name,
+                        SymbolKind::Label,
+                        address,
+                        &mut diagnostics,
+                    );
+                }
+                None => symbols.define_anonymous(x.label, address),
             }
-            binary_header.push_all(x.iter().copied());
         }
+        // the data can refer to the labels
+        for (segment, values) in segments.iter_mut().zip(&deferred) {
+            data::resolve(segment, values, &symbols, &mut diagnostics);
+        }
+        let header = Header {
+            version,
+            entrypoint: entry_offset + header_size,
+            segments,
+        };
+        let binary_header = header.encode().unwrap_or_else(|e| {
+            diagnostics.push(Diagnostic::new(
+                DiagnosticKind::Overflow,
+                file,
+                e.to_string(),
+            ));
+            Vec::new()
+        });
 
         Ok(Self {
-            consts,
-            labels,
+            symbols,
             sections,
+            header,
             binary_header,
+            diagnostics,
         })
     }
 
-    fn read_labels(code_section_lines: &[String]) -> HashMap<String, u16> {
-        let mut map = HashMap::new();
+    /// Returns the entrypoint offset in the code.
+    fn find_entrypoint(
+        sections: &Sections,
+        labels: &HashMap<&str, u16>,
+        file: &str,
+    ) -> Result<u16, Diagnostic> {
+        let entry_section = sections.find("entry").ok_or(Diagnostic::new(
+            DiagnosticKind::MissingSection,
+            file,
+            "Missing .entry section",
+        ))?;
+        let title = &entry_section.title;
+        let args = entry_section.args();
+        let &entrypoint = args.first().ok_or(title.error(
+            title.text.trim(),
+            DiagnosticKind::Syntax,
+            ".entry: missing entrypoint",
+        ))?;
+
+        labels.get(entrypoint).copied().ok_or(title.error(
+            entrypoint,
+            DiagnosticKind::UndefinedSymbol,
+            format!("Cannot find entrypoint: {entrypoint}"),
+        ))
+    }
+
+    /// Returns the labels in order.
+    fn read_labels<'a>(
+        code_section_lines: &'a [SourceLine],
+        diagnostics: &mut Vec<Diagnostic>,
+    ) -> Vec<LabelDef<'a>> {
+        let mut labels = Vec::new();
         let mut offset = 0_u16;
-        for line in code_section_lines {
-            let line = Self::remove_comment(line.trim());
+        let mut scope = None;
+        for source_line in code_section_lines {
+            let line = Self::remove_comment(source_line.text.trim());
             if line.is_empty() {
                 continue;
             }
             if let Some(x) = line.strip_suffix(':') {
-                map.insert(x.into(), offset);
+                if symbol::is_scope(x) {
+                    scope = Some(x);
+                }
+                let name = if symbol::is_anonymous(x) {
+                    None
+                } else if let Some(name) = symbol::qualify(x, scope) {
+                    Some(name.into_owned())
+                } else {
+                    diagnostics.push(source_line.error(
+                        x,
+                        DiagnosticKind::Syntax,
+                        format!("Local label `{x}` has no global label before it"),
+                    ));
+                    continue;
+                };
+                labels.push(LabelDef {
+                    line: source_line,
+                    label: x,
+                    name,
+                    offset,
+                });
                 continue;
             }
             offset += INST_LENGTH as u16;
         }
-        map
+        labels
+    }
+
+    /// Defines `name`, or reports `part`, where it's written in `line`, if it's
+    /// already defined. Returns whether it's defined.
+    fn define_symbol(
+        symbols: &mut SymbolTable,
+        line: &SourceLine,
+        part: &str,
+        name: &str,
+        kind: SymbolKind,
+        value: u16,
+        diagnostics: &mut Vec<Diagnostic>,
+    ) -> bool {
+        // only constants may shadow a register
+        if kind != SymbolKind::Const && OperandSymbol::from_str(name).is_ok() {
+            diagnostics.push(line.error(
+                part,
+                DiagnosticKind::DuplicateSymbol,
+                format!("`{name}` is already defined as a register"),
+            ));
+            return false;
+        }
+        if let Err(existing) = symbols.define(name, kind, value) {
+            diagnostics.push(line.error(
+                part,
+                DiagnosticKind::DuplicateSymbol,
+                format!("`{name}` is already defined as a {}", existing.kind),
+            ));
+            return false;
+        }
+        true
     }
 
-    pub fn assemble(&self) -> AssemblyTarget {
+    pub fn assemble(&self) -> Result<AssemblyTarget, Diagnostics> {
+        let mut diagnostics = self.diagnostics.clone();
         let mut commented_binary = String::new();
 
         let mut commented_binary_append = |b: &[u8], comment: &str| {
@@ -202,214 +344,539 @@ impl Assembler {
             }
         };
 
-        commented_binary_append(&self.binary_header[0..4], "copystatic");
-        commented_binary_append(&self.binary_header[4..], "data");
+        let data_start = self.header.size()
+            - self
+                .header
+                .segments
+                .iter()
+                .map(|x| x.data.len())
+                .sum::<usize>();
+        let (fields, data) = self
+            .binary_header
+            .split_at(data_start.min(self.binary_header.len()));
+        commented_binary_append(
+            fields,
+            match self.header.version {
+                HeaderVersion::V1 => "copystatic",
+                HeaderVersion::V2 => "header v2",
+            },
+        );
+        commented_binary_append(data, "data");
 
         let mut code_binary = Vec::new();
+        let mut debug_lines = Vec::new();
+        let mut listing_rows = Vec::new();
+        let mut current_label = None;
+        // the global label of the local ones
+        let mut label_scope = None;
         let code_section = self.sections.find("code").unwrap();
-        for line in &code_section.body_lines {
-            let line = Self::remove_comment(line.trim());
+        for source_line in &code_section.body_lines {
+            let line = Self::remove_comment(source_line.text.trim());
+            let address = (self.binary_header.len() + code_binary.len()) as u16;
+            let mut listing_row = |bytes: &[u8]| {
+                if !source_line.text.trim().is_empty() {
+                    listing_rows.push(listing::Row {
+                        address,
+                        bytes: bytes.into(),
+                        file: source_line.file.to_string(),
+                        line: source_line.line,
+                        source: source_line.annotate(source_line.text.trim()),
+                    });
+                }
+            };
             // skip labels and empty lines
             if line.ends_with(':') || line.is_empty() {
-                commented_binary_append(&[], line);
+                if let Some(x) = line.strip_suffix(':') {
+                    if symbol::is_scope(x) {
+                        label_scope = Some(x);
+                    }
+                    if !symbol::is_anonymous(x)
+                        && let Some(name) = symbol::qualify(x, label_scope)
+                    {
+                        current_label = Some(name.into_owned());
+                    }
+                }
+                commented_binary_append(&[], &source_line.annotate(line));
+                listing_row(&[]);
                 continue;
             }
 
-            let inst = self.process_asm_statement(line).unwrap();
+            let position = Position {
+                scope: label_scope,
+                address,
+            };
+            let inst = match self.process_asm_statement(source_line, line, position) {
+                Ok(x) => x,
+                Err(e) => {
+                    diagnostics.push(e);
+                    // keep the addresses of the following instructions
+                    NULL_INSTRUCTION
+                }
+            };
+            debug_lines.push(LineInfo {
+                address,
+                file: source_line.file.to_string(),
+                line: source_line.line,
+                label: current_label.clone(),
+                statement: line.into(),
+            });
             inst.iter().for_each(|&x| code_binary.push(x));
-            commented_binary_append(&inst, line);
+            commented_binary_append(&inst, &source_line.annotate(line));
+            listing_row(&inst);
+        }
+
+        if !diagnostics.is_empty() {
+            yeet!(Diagnostics(diagnostics));
         }
 
         let binary_parts = BinaryParts {
             header: self.binary_header.clone(),
             code: code_binary,
         };
-        AssemblyTarget {
+        let labels = self.symbols.of_kind(SymbolKind::Label);
+        let listing = listing::render(
+            &listing_rows,
+            &self.header,
+            &self.binary_header,
+            &self.symbols,
+        );
+        Ok(AssemblyTarget {
             binary: binary_parts,
             commented_binary,
-        }
+            listing,
+            labels: labels.clone(),
+            symbols: self.symbols.clone(),
+            debug_info: DebugInfo {
+                labels: labels.into_iter().collect(),
+                lines: debug_lines,
+            },
+        })
     }
 
-    fn process_asm_statement(&self, line: &str) -> anyhow::Result<[u8; 4]> {
-        let mut inst = [0_u8; 4];
-
-        let split = line.split(' ').collect::<Vec<_>>();
-        let &opcode_str = split.first().ok_or(anyhow!("Missing opcode"))?;
-        let opcode =
-            Opcode::from_str(opcode_str).map_err(|_| anyhow!("Unknown opcode: {}", opcode_str))?;
-        inst[0] = opcode as u8;
+    /// `statement` is the comment-stripped part of `line`, and `position` is
+    /// where it's assembled to.
+    fn process_asm_statement(
+        &self,
+        line: &SourceLine,
+        statement: &str,
+        position: Position,
+    ) -> Result<[u8; 4], Diagnostic> {
+        let split = expr::split_operands(statement);
+        let opcode_str = split[0];
+        let opcode = Opcode::from_str(opcode_str).map_err(|_| {
+            line.error(
+                opcode_str,
+                DiagnosticKind::UnknownOpcode,
+                format!("Unknown opcode: {opcode_str}"),
+            )
+        })?;
+
+        let operand_count = opcode.asm_operand_count();
+        let given_operands = &split[1..];
+        if given_operands.len() != operand_count {
+            let span = match given_operands.get(operand_count) {
+                // point to the first redundant operand
+                Some(&x) => x,
+                None => statement,
+            };
+            yeet!(line.error(
+                span,
+                DiagnosticKind::OperandCount,
+                format!(
+                    "`{opcode_str}` takes {operand_count} operand(s) but {} given",
+                    given_operands.len()
+                ),
+            ));
+        }
 
         // special handles for opcodes that have 16-bit immediate operands
         let operands = if opcode == Opcode::JumpAddrMove || opcode == Opcode::Call {
-            // `jamv` and `call` only support label operands for now
-            let label = split[1];
-            let Some(&label) = self.labels.get(label) else {
-                yeet!(anyhow!("Label not found: {label}"))
+            let scope = Scope {
+                symbols: &self.symbols,
+                usage: Usage::CodeAddress,
+                position: Some(position),
             };
+            let target = split[1];
+            let label = expr::evaluate_u16(target, &scope)
+                .map_err(|e| line.error(e.part, e.kind, e.message))?;
             let high = (label >> 8) as u8;
             let low = (label & 0x00ff_u16) as u8;
             // LEG uses small-endianness
             vec![Operand::Immediate(low), Operand::Immediate(high)]
         } else {
-            split[1..]
+            let scope = Scope {
+                symbols: &self.symbols,
+                usage: Usage::Byte,
+                position: Some(position),
+            };
+            given_operands
                 .iter()
                 .map(|&x| match x {
-                    _ if let Some(&x) = self.consts.get(x) => Ok(Operand::Immediate(x)),
-                    _ => Operand::from_str(x).map_err(|_| anyhow!("Cannot parse operand: {x}")),
+                    // a constant shadows the register of the same name
+                    _ if self
+                        .symbols
+                        .get(x)
+                        .is_none_or(|s| s.kind != SymbolKind::Const)
+                        && let Ok(x) = OperandSymbol::from_str(x) =>
+                    {
+                        Ok(Operand::Symbol(x))
+                    }
+                    _ => expr::evaluate_u8(x, &scope)
+                        .map(Operand::Immediate)
+                        .map_err(|e| match e.kind {
+                            // neither a register nor a known name
+                            DiagnosticKind::UndefinedSymbol
+                                if e.part == x
+                                    && !x
+                                        .strip_suffix(['f', 'b'])
+                                        .is_some_and(symbol::is_anonymous) =>
+                            {
+                                line.error(
+                                    x,
+                                    DiagnosticKind::InvalidOperand,
+                                    format!("Cannot parse operand: {x}"),
+                                )
+                            }
+                            _ => line.error(e.part, e.kind, e.message),
+                        }),
                 })
                 .collect::<Result<Vec<_>, _>>()?
         };
 
-        opcode.binary(&operands)
+        opcode
+            .binary(&operands)
+            .map_err(|e| line.error(statement, DiagnosticKind::InvalidOperand, e.to_string()))
     }
 
     fn remove_comment(line: &str) -> &str {
-        if let Some(x) = line.split_once(';') {
-            x.0.trim()
-        } else {
-            line
+        match Self::comment_start(line) {
+            Some(i) => line[..i].trim(),
+            None => line,
+        }
+    }
+
+    /// Index of the first `;` which is not in a quoted character or string.
+    fn comment_start(line: &str) -> Option<usize> {
+        let mut quote = None;
+        let mut escaped = false;
+        for (i, c) in line.char_indices() {
+            match quote {
+                _ if escaped => escaped = false,
+                Some(_) if c == '\\' => escaped = true,
+                Some(q) if c == q => quote = None,
+                Some(_) => {}
+                None if c == '\'' || c == '"' => quote = Some(c),
+                None if c == ';' => return Some(i),
+                None => {}
+            }
+        }
+        None
+    }
+}
+
+/// A `label:` line in `.code`.
+struct LabelDef<'a> {
+    line: &'a SourceLine,
+    /// As written, without the colon.
+    label: &'a str,
+    /// The full name, or `None` for an anonymous label.
+    name: Option<String>,
+    /// Offset in the code.
+    offset: u16,
+}
+
+#[derive(Debug, Clone)]
+struct SourceLine {
+    file: Rc<str>,
+    /// 1-based line number.
+    line: usize,
+    text: String,
+    /// The macro invocation this line comes from. `text` is then the
+    /// substituted macro body line.
+    expansion: Option<Rc<Expansion>>,
+}
+
+impl SourceLine {
+    /// Byte range of `part` in the line text. `part` must be a substring slice
+    /// of `self.text`.
+    fn span_of(&self, part: &str) -> Range<usize> {
+        let start = part.as_ptr() as usize - self.text.as_ptr() as usize;
+        debug_assert!(start + part.len() <= self.text.len());
+        start..(start + part.len())
+    }
+
+    fn error(&self, part: &str, kind: DiagnosticKind, message: impl Into<String>) -> Diagnostic {
+        let mut diagnostic = Diagnostic::new(kind, &*self.file, message).at(
+            self.line,
+            &self.text,
+            self.span_of(part),
+        );
+        for x in self.expansions() {
+            let call_site = &x.call_site;
+            let name = call_site.text.split_whitespace().next().unwrap();
+            diagnostic = diagnostic.expanded_from(
+                &x.name,
+                x.pseudo,
+                &*call_site.file,
+                call_site.line,
+                &call_site.text,
+                call_site.span_of(name),
+            );
         }
+        diagnostic
+    }
+
+    /// The macro and pseudo-instruction invocations this line comes from,
+    /// innermost first.
+    fn expansions(&self) -> impl Iterator<Item = &Expansion> {
+        std::iter::successors(self.expansion.as_deref(), |x| {
+            x.call_site.expansion.as_deref()
+        })
+    }
+
+    /// Appends the invocations this line comes from to `statement`, for
+    /// `commented_binary`.
+    fn annotate(&self, statement: &str) -> String {
+        let mut annotated = statement.to_string();
+        for x in self.expansions() {
+            let call = Assembler::remove_comment(x.call_site.text.trim());
+            let call_site = &x.call_site;
+            annotated.push_str(&format!(
+                " <- {call} ({}:{})",
+                call_site.file, call_site.line
+            ));
+        }
+        annotated
     }
 }
 
-#[derive(Debug, Clone, Default)]
+#[derive(Debug, Clone)]
 struct Section {
     name: String,
-    args: Vec<String>,
-    body_lines: Vec<String>,
+    /// The line starting with `.`.
+    title: SourceLine,
+    body_lines: Vec<SourceLine>,
+    /// Titles of the same sections in included files, appended to this one.
+    merged: Vec<SourceLine>,
 }
 
 impl Section {
-    fn new(title_line: impl AsRef<str>) -> Section {
-        let mut split = title_line.as_ref().split(' ');
-        let name = split.next().unwrap();
-        let args = split.map(Into::into).collect::<Vec<String>>();
+    fn new(title: SourceLine) -> Section {
+        let name = title.text[1..]
+            .split_whitespace()
+            .next()
+            .unwrap_or_default()
+            .into();
         Self {
-            name: name.into(),
-            args,
-            ..Default::default()
+            name,
+            title,
+            body_lines: Vec::new(),
+            merged: Vec::new(),
         }
     }
+
+    /// Arguments after the section name.
+    fn args(&self) -> Vec<&str> {
+        self.arg_text().split_whitespace().collect()
+    }
+
+    /// The start address and the segment name of a `.data` section. The name
+    /// is empty if not given.
+    ///
+    /// The name is the last word if it follows an operand, not an operator, as
+    /// the start address may have spaces in it.
+    fn data_args(&self) -> (&str, &str) {
+        let args = self.arg_text();
+        match args.rsplit_once(char::is_whitespace) {
+            Some((start, name))
+                if name.starts_with(|x: char| x.is_alphabetic() || x == '_')
+                    && name.chars().all(|x| x.is_alphanumeric() || x == '_')
+                    && start
+                        .trim_end()
+                        .ends_with(|x: char| x.is_alphanumeric() || "_)'".contains(x)) =>
+            {
+                (start.trim_end(), name)
+            }
+            _ => (args, ""),
+        }
+    }
+
+    fn segment_name(&self) -> &str {
+        match self.name.as_str() {
+            "data" => self.data_args().1,
+            _ => "",
+        }
+    }
+
+    /// Whether `other` is the same section, which for `.data` means the same
+    /// segment.
+    fn is_same(&self, other: &Section) -> bool {
+        self.name == other.name && self.segment_name() == other.segment_name()
+    }
+
+    /// Everything after the section name, with the comment removed.
+    fn arg_text(&self) -> &str {
+        let title = Assembler::remove_comment(self.title.text.trim());
+        let name_end = title.find(char::is_whitespace).unwrap_or(title.len());
+        title[name_end..].trim()
+    }
 }
 
 #[derive(Debug, Clone)]
 struct Sections {
     sections: Vec<Section>,
+    /// `.macro` definitions. They can be put anywhere, and don't end the
+    /// section they're in.
+    macros: Vec<Section>,
+    /// `.include` directives. Like `.macro`, they don't end the current section.
+    includes: Vec<Section>,
 }
 
+/// Whether `line` starts a section or is another directive, like `.macro`.
+/// Local labels, `.name:`, are not.
+fn is_directive(line: &str) -> bool {
+    line.starts_with('.') && !Assembler::remove_comment(line.trim()).ends_with(':')
+}
+
+/// Sections that can be spread over several files.
+const MERGEABLE_SECTIONS: [&str; 3] = ["consts", "data", "code"];
+
 impl Sections {
-    fn new(code: &str) -> anyhow::Result<Self> {
+    /// Parses one file. The includes are not resolved.
+    fn new(code: &str, file: &str, diagnostics: &mut Vec<Diagnostic>) -> Self {
+        let file: Rc<str> = file.into();
         let mut sections = Vec::new();
+        let mut macros = Vec::new();
+        let mut includes = Vec::new();
         let mut current: Option<Section> = None;
-        for line in code.lines() {
-            if line.starts_with('.') {
+        let mut current_macro: Option<Section> = None;
+        for (line_no, line) in code.lines().enumerate() {
+            let line = SourceLine {
+                file: Rc::clone(&file),
+                line: line_no + 1,
+                text: line.into(),
+                expansion: None,
+            };
+            if let Some(ref mut x) = current_macro {
+                if !is_directive(&line.text) {
+                    if !line.text.trim().is_empty() {
+                        x.body_lines.push(line);
+                    }
+                    continue;
+                }
+                if Section::new(line.clone()).name == "endm" {
+                    macros.extend(current_macro.take());
+                } else {
+                    diagnostics.push(line.error(
+                        line.text.trim_end(),
+                        DiagnosticKind::Syntax,
+                        "Directives are not allowed in a macro body",
+                    ));
+                }
+                continue;
+            }
+            if is_directive(&line.text) {
+                let section = Section::new(line);
+                match section.name.as_str() {
+                    "macro" => {
+                        current_macro = Some(section);
+                        continue;
+                    }
+                    "include" => {
+                        includes.push(section);
+                        continue;
+                    }
+                    "endm" => {
+                        let title = &section.title;
+                        diagnostics.push(title.error(
+                            title.text.trim_end(),
+                            DiagnosticKind::Syntax,
+                            "`.endm` without `.macro`",
+                        ));
+                        continue;
+                    }
+                    _ => {}
+                }
                 if let Some(x) = current.take() {
                     sections.push(x);
                 }
 
-                current = Some(Section::new(line.strip_prefix('.').unwrap()));
+                current = Some(section);
                 continue;
             }
             if let Some(ref mut x) = current
-                && !line.is_empty()
+                && !line.text.is_empty()
             {
-                x.body_lines.push(line.into());
+                x.body_lines.push(line);
             }
         }
 
         if let Some(x) = current.take() {
             sections.push(x);
         }
-
-        let iter = sections.iter().map(|x| &x.name);
-        if let Some(d) = find_duplicates(iter) {
-            yeet!(anyhow::anyhow!("Duplicated section not allowed: .{}", d));
+        if let Some(x) = current_macro {
+            let title = &x.title;
+            diagnostics.push(title.error(
+                title.text.trim_end(),
+                DiagnosticKind::Syntax,
+                "Unterminated macro: missing `.endm`",
+            ));
         }
-        Ok(Self { sections })
-    }
 
-    fn find(&self, name: &str) -> Option<&Section> {
-        self.sections.iter().find(|x| x.name == name)
+        for (i, x) in sections.iter().enumerate() {
+            if sections[..i].iter().any(|s| s.is_same(x)) {
+                let title = &x.title;
+                diagnostics.push(title.error(
+                    title.text.trim_end(),
+                    DiagnosticKind::DuplicateSection,
+                    format!("Duplicated section not allowed: .{}", x.name),
+                ));
+            }
+        }
+        Self {
+            sections,
+            macros,
+            includes,
+        }
     }
-}
 
-fn find_duplicates<T>(iter: impl Iterator<Item = T>) -> Option<T>
-where
-    T: Eq + PartialEq + Hash + Copy,
-{
-    let mut set = HashSet::new();
-    for x in iter {
-        if set.contains(&x) {
-            return Some(x);
+    /// Appends the sections of an included file to the same-named ones.
+    fn merge(&mut self, included: Sections, diagnostics: &mut Vec<Diagnostic>) {
+        for x in included.sections {
+            match self.sections.iter_mut().find(|s| s.is_same(&x)) {
+                Some(s) if MERGEABLE_SECTIONS.contains(&x.name.as_str()) => {
+                    s.body_lines.extend(x.body_lines);
+                    s.merged.push(x.title);
+                    s.merged.extend(x.merged);
+                }
+                Some(_) => {
+                    let title = &x.title;
+                    diagnostics.push(title.error(
+                        title.text.trim_end(),
+                        DiagnosticKind::DuplicateSection,
+                        format!("Duplicated section not allowed: .{}", x.name),
+                    ));
+                }
+                None => self.sections.push(x),
+            }
         }
-        set.insert(x);
+        self.macros.extend(included.macros);
     }
-    None
-}
-
-fn regex_capture(regex: Regex, haystack: &str) -> Option<Vec<&'_ str>> {
-    regex
-        .captures(haystack)?
-        .iter()
-        .map(|x| x.map(|x| x.as_str()))
-        .collect()
-}
-
-macro regex($x:expr) {
-    ::regex::Regex::new($x).unwrap()
-}
-
-trait RegexExt {
-    fn capture_vec(self, haystack: &str) -> Option<Vec<&str>>;
-}
 
-impl RegexExt for Regex {
-    fn capture_vec(self, haystack: &str) -> Option<Vec<&str>> {
-        regex_capture(self, haystack)
+    /// Finds the first section named `name`.
+    fn find(&self, name: &str) -> Option<&Section> {
+        self.sections.iter().find(|x| x.name == name)
     }
-}
-
-fn parse_quoted_string(s: &str) -> Option<String> {
-    s.starts_with("'").then_some(())?;
-    s.ends_with("'").then_some(())?;
 
-    let content = &s[1..(s.len() - 1)];
-    Some(content.replace("''", "'"))
-}
-
-fn parse_data_array(s: &str) -> Option<Vec<u8>> {
-    s.starts_with("[").then_some(())?;
-    s.ends_with("]").then_some(())?;
-    let content = &s[1..(s.len() - 1)];
-    if content.trim().is_empty() {
-        return Some(vec![]);
+    fn find_all<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a Section> {
+        self.sections.iter().filter(move |x| x.name == name)
     }
-    let mut items = Vec::new();
-    for x in content.split(",") {
-        items.push(parse_u8_literal(x.trim())?);
-    }
-    Some(items)
-}
 
-fn parse_data_value(value: &str) -> Option<DataValue> {
-    match value {
-        _ if value.starts_with("'") && value.ends_with("'") => {
-            Some(DataValue::String(parse_quoted_string(value)?.into()))
-        }
-        _ if value.starts_with("[") && value.ends_with("]") => {
-            Some(DataValue::Array(parse_data_array(value)?))
-        }
-        _ if value.parse::<u8>().is_ok() => Some(DataValue::Byte(value.parse().unwrap())),
-        _ => None,
+    fn find_mut(&mut self, name: &str) -> Option<&mut Section> {
+        self.sections.iter_mut().find(|x| x.name == name)
     }
 }
 
-enum DataValue {
-    String(Vec<u8>),
-    Array(Vec<u8>),
-    Byte(u8),
-}
-
 fn hex_array_literal(binary: &[u8]) -> String {
     let mut line = String::new();
     for &x in binary {
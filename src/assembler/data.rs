@@ -0,0 +1,520 @@
+//! `.data` entries.
+//!
+//! ```text
+//! .data <mem_start> [<segment-name>]
+//! <name> <value> [<length-name>]
+//! align <n>
+//! ```
+//!
+//! Each `.data` section is a segment, copied to RAM at `mem_start`. Sections
+//! of the same segment name in included files are merged into one, which
+//! they must start at the same address as, and the segments can't overlap.
+//!
+//! A value is one of:
+//!
+//! - `'text'` or `"text"`, with the escapes `\n`, `\t`, `\r`, `\0`, `\\`,
+//!   `\'`, `\"` and `\xHH`; `''` is also a quote in `'text'`
+//! - `asciz 'text'`, the text followed by a zero byte
+//! - `[a, b, ...]`, bytes
+//! - `word a` or `word [a, b, ...]`, 16-bit little-endian words
+//! - `times <n> <value>`, the value repeated `n` times
+//! - `reserve <n>`, `n` zero bytes
+//! - a single byte
+//!
+//! Bytes and words are expressions, and can refer to any symbol, including the
+//! code labels. The counts and the alignment can only refer to constants and
+//! the `.data` entries above. The length name, if given and not `_`, is defined
+//! as the length of the value in bytes. `align` pads with zeros until the
+//! address is a multiple of `n`.
+
+use crate::assembler::diagnostic::{Diagnostic, DiagnosticKind};
+use crate::assembler::expr::{self, Scope, Usage};
+use crate::assembler::symbol::{SymbolKind, SymbolTable};
+use crate::assembler::{Assembler, Section, SourceLine};
+use crate::header::DataSegment;
+
+/// A value evaluated once the labels are defined.
+#[derive(Copy, Clone)]
+pub(super) struct Deferred<'a> {
+    line: &'a SourceLine,
+    expr: &'a str,
+    /// Offset in the segment.
+    offset: usize,
+    word: bool,
+}
+
+/// The data of `.data` entries can't go beyond this address.
+const ADDRESS_SPACE: usize = 0x100;
+
+/// Lays out `section` and defines its symbols. The bytes of the deferred
+/// values are left zero, to be filled by [`resolve`].
+/// The `mem_start` of a `.data` section. Errors are reported.
+fn evaluate_start(
+    section: &Section,
+    symbols: &SymbolTable,
+    diagnostics: &mut Vec<Diagnostic>,
+) -> Option<u8> {
+    match section.data_args().0 {
+        "" => {
+            diagnostics.push(section.title.error(
+                section.title.text.trim(),
+                DiagnosticKind::Syntax,
+                ".data: missing mem_start",
+            ));
+            None
+        }
+        x => {
+            let scope = Scope {
+                symbols,
+                usage: Usage::Const,
+                position: None,
+            };
+            expr::evaluate_u8(x, &scope)
+                .map_err(|e| diagnostics.push(section.title.error(e.part, e.kind, e.message)))
+                .ok()
+        }
+    }
+}
+
+pub(super) fn layout<'a>(
+    section: &'a Section,
+    symbols: &mut SymbolTable,
+    diagnostics: &mut Vec<Diagnostic>,
+) -> (DataSegment, Vec<Deferred<'a>>) {
+    let mem_start = evaluate_start(section, symbols, diagnostics).unwrap_or(0);
+    // the bytes of an included one follow the ones here, so it must not mean
+    // another address
+    for title in &section.merged {
+        let merged = Section::new(title.clone());
+        if let Some(x) = evaluate_start(&merged, symbols, diagnostics)
+            && x != mem_start
+        {
+            diagnostics.push(title.error(
+                title.text.trim(),
+                DiagnosticKind::DuplicateSection,
+                format!(
+                    "`{}` is merged into the segment at 0x{mem_start:02x} of the including file; \
+                     give it another segment name",
+                    title.text.trim()
+                ),
+            ));
+        }
+    }
+
+    let mut entries = Entries {
+        mem_start,
+        data: Vec::new(),
+        deferred: Vec::new(),
+        name: "",
+    };
+    for source_line in &section.body_lines {
+        let line = Assembler::remove_comment(source_line.text.trim());
+        if line.is_empty() {
+            continue;
+        }
+        if let Err(e) = entries.entry(source_line, line, symbols, diagnostics) {
+            diagnostics.push(e);
+        }
+    }
+    let segment = DataSegment {
+        mem_start,
+        data: entries.data,
+    };
+    (segment, entries.deferred)
+}
+
+/// Reports the segments that share addresses with an earlier one, at their
+/// `titles`.
+pub(super) fn check_overlaps(
+    segments: &[DataSegment],
+    titles: &[&SourceLine],
+    diagnostics: &mut Vec<Diagnostic>,
+) {
+    let range = |x: &DataSegment| x.mem_start as usize..(x.mem_start as usize + x.data.len());
+    for (i, x) in segments.iter().enumerate() {
+        let r = range(x);
+        let overlapped = segments[..i].iter().zip(titles).find(|(y, _)| {
+            let s = range(y);
+            r.start < s.end && s.start < r.end
+        });
+        if let Some((y, title)) = overlapped {
+            let s = range(y);
+            let title_text = titles[i].text.trim();
+            diagnostics.push(titles[i].error(
+                title_text,
+                DiagnosticKind::Overlap,
+                format!(
+                    "Data at {:#04x}..{:#04x} overlaps `{}` at {:#04x}..{:#04x}",
+                    r.start,
+                    r.end,
+                    Assembler::remove_comment(title.text.trim()),
+                    s.start,
+                    s.end
+                ),
+            ));
+        }
+    }
+}
+
+/// Evaluates the deferred values into `segment`.
+pub(super) fn resolve(
+    segment: &mut DataSegment,
+    deferred: &[Deferred],
+    symbols: &SymbolTable,
+    diagnostics: &mut Vec<Diagnostic>,
+) {
+    for x in deferred {
+        let scope = Scope {
+            symbols,
+            usage: if x.word { Usage::Word } else { Usage::Byte },
+            position: None,
+        };
+        let result = if x.word {
+            expr::evaluate_u16(x.expr, &scope).map(u16::to_le_bytes)
+        } else {
+            expr::evaluate_u8(x.expr, &scope).map(|x| [x, 0])
+        };
+        match result {
+            Ok(bytes) => {
+                let len = if x.word { 2 } else { 1 };
+                segment.data[x.offset..(x.offset + len)].copy_from_slice(&bytes[..len]);
+            }
+            Err(e) => diagnostics.push(x.line.error(e.part, e.kind, e.message)),
+        }
+    }
+}
+
+struct Entries<'a> {
+    mem_start: u8,
+    data: Vec<u8>,
+    deferred: Vec<Deferred<'a>>,
+    /// Name of the entry being parsed, for the errors.
+    name: &'a str,
+}
+
+impl<'a> Entries<'a> {
+    /// Parses one line. On errors, nothing is added.
+    fn entry(
+        &mut self,
+        source_line: &'a SourceLine,
+        line: &'a str,
+        symbols: &mut SymbolTable,
+        diagnostics: &mut Vec<Diagnostic>,
+    ) -> Result<(), Diagnostic> {
+        let (name, rest) = split_word(line);
+        self.name = name;
+        if name == "align" {
+            let (n, rest) = expr::split_first_operand(rest);
+            if !rest.is_empty() || n.is_empty() {
+                return Err(syntax_error(
+                    source_line,
+                    line,
+                    ".data: expected `align <n>`",
+                ));
+            }
+            let n = count(source_line, n, symbols)?.max(1);
+            let address = self.mem_start as usize + self.data.len();
+            let padding = address.next_multiple_of(n) - address;
+            self.check_space(source_line, line, padding)?;
+            self.data.resize(self.data.len() + padding, 0);
+            return Ok(());
+        }
+        if rest.is_empty() {
+            return Err(syntax_error(
+                source_line,
+                line,
+                ".data: expected `<name> <value> [<length-name>]`",
+            ));
+        }
+
+        let start = self.data.len();
+        let deferred_start = self.deferred.len();
+        let result = self.value(source_line, rest, symbols).and_then(|after| {
+            let value = rest[..(rest.len() - after.len())].trim_end();
+            self.check_space(source_line, value, 0)?;
+            match after.split_whitespace().collect::<Vec<_>>()[..] {
+                [] => Ok(None),
+                ["_"] => Ok(None),
+                [x] => Ok(Some(x)),
+                _ => Err(syntax_error(
+                    source_line,
+                    after.trim(),
+                    ".data: unexpected text after the value",
+                )),
+            }
+        });
+        let length_name = match result {
+            Ok(x) => x,
+            Err(e) => {
+                self.data.truncate(start);
+                self.deferred.truncate(deferred_start);
+                return Err(e);
+            }
+        };
+
+        let address = self.mem_start as u16 + start as u16;
+        let len = (self.data.len() - start) as u16;
+        if Assembler::define_symbol(
+            symbols,
+            source_line,
+            name,
+            name,
+            SymbolKind::Data,
+            address,
+            diagnostics,
+        ) {
+            symbols.set_size(name, len);
+        }
+        if let Some(x) = length_name {
+            Assembler::define_symbol(
+                symbols,
+                source_line,
+                x,
+                x,
+                SymbolKind::Const,
+                len,
+                diagnostics,
+            );
+        }
+        Ok(())
+    }
+
+    /// Reports `part` if the data with `len` more bytes doesn't fit in the
+    /// address space.
+    fn check_space(&self, line: &SourceLine, part: &str, len: usize) -> Result<(), Diagnostic> {
+        let end = self.mem_start as usize + self.data.len() + len;
+        if end > ADDRESS_SPACE {
+            return Err(line.error(
+                part,
+                DiagnosticKind::Overflow,
+                format!(
+                    ".data: `{}` ends at 0x{end:03x}, beyond the 8-bit address space",
+                    self.name
+                ),
+            ));
+        }
+        Ok(())
+    }
+
+    /// Parses the value at the start of `s`. Returns the rest of `s`.
+    fn value(
+        &mut self,
+        line: &'a SourceLine,
+        s: &'a str,
+        symbols: &SymbolTable,
+    ) -> Result<&'a str, Diagnostic> {
+        let (keyword, after) = split_word(s);
+        match keyword {
+            "asciz" => {
+                let (text, rest) = string(line, after)?;
+                self.data.extend(text);
+                self.data.push(0);
+                Ok(rest)
+            }
+            "reserve" => {
+                let (n, rest) = expr::split_first_operand(after);
+                let n = count(line, n, symbols)?;
+                self.check_space(line, s, n)?;
+                self.data.resize(self.data.len() + n, 0);
+                Ok(rest)
+            }
+            "times" => {
+                let (n, rest) = expr::split_first_operand(after);
+                let n = count(line, n, symbols)?;
+                let start = self.data.len();
+                let deferred_start = self.deferred.len();
+                let rest = self.value(line, rest, symbols)?;
+                let len = self.data.len() - start;
+                let count_deferred = self.deferred.len() - deferred_start;
+                if n == 0 {
+                    self.data.truncate(start);
+                    self.deferred.truncate(deferred_start);
+                    return Ok(rest);
+                }
+                self.check_space(line, s, len * (n - 1))?;
+                for i in 1..n {
+                    self.data.extend_from_within(start..(start + len));
+                    for j in deferred_start..(deferred_start + count_deferred) {
+                        let x = self.deferred[j];
+                        self.deferred.push(Deferred {
+                            offset: x.offset + i * len,
+                            ..x
+                        });
+                    }
+                }
+                Ok(rest)
+            }
+            "word" if after.starts_with('[') => {
+                let (items, rest) = array(line, after)?;
+                for x in items {
+                    self.defer(line, x, true);
+                }
+                Ok(rest)
+            }
+            "word" => {
+                let (x, rest) = expr::split_first_operand(after);
+                if x.is_empty() {
+                    return Err(syntax_error(line, s, ".data: expected a word value"));
+                }
+                self.defer(line, x, true);
+                Ok(rest)
+            }
+            _ if s.starts_with(['\'', '"']) => {
+                let (text, rest) = string(line, s)?;
+                self.data.extend(text);
+                Ok(rest)
+            }
+            _ if s.starts_with('[') => {
+                let (items, rest) = array(line, s)?;
+                for x in items {
+                    self.defer(line, x, false);
+                }
+                Ok(rest)
+            }
+            _ => {
+                let (x, rest) = expr::split_first_operand(s);
+                self.defer(line, x, false);
+                Ok(rest)
+            }
+        }
+    }
+
+    fn defer(&mut self, line: &'a SourceLine, expr: &'a str, word: bool) {
+        self.deferred.push(Deferred {
+            line,
+            expr,
+            offset: self.data.len(),
+            word,
+        });
+        let len = if word { 2 } else { 1 };
+        self.data.resize(self.data.len() + len, 0);
+    }
+}
+
+fn syntax_error(line: &SourceLine, part: &str, message: &str) -> Diagnostic {
+    line.error(part, DiagnosticKind::Syntax, message)
+}
+
+/// Splits off the first whitespace-separated word.
+fn split_word(s: &str) -> (&str, &str) {
+    let end = s.find(char::is_whitespace).unwrap_or(s.len());
+    (&s[..end], s[end..].trim_start())
+}
+
+/// Evaluates the count of `times`, `reserve` or `align`.
+fn count(line: &SourceLine, expr: &str, symbols: &SymbolTable) -> Result<usize, Diagnostic> {
+    if expr.is_empty() {
+        return Err(syntax_error(line, expr, ".data: expected a count"));
+    }
+    let scope = Scope {
+        symbols,
+        usage: Usage::Const,
+        position: None,
+    };
+    let n = expr::evaluate(expr, &scope).map_err(|e| line.error(e.part, e.kind, e.message))?;
+    if !(0..=ADDRESS_SPACE as i64).contains(&n) {
+        return Err(line.error(
+            expr,
+            DiagnosticKind::Overflow,
+            format!("`{expr}` is {n}, which is not a count of 0 to {ADDRESS_SPACE}"),
+        ));
+    }
+    Ok(n as usize)
+}
+
+/// Parses the string literal at the start of `s`. Returns its bytes and the
+/// rest of `s`.
+fn string<'s>(line: &SourceLine, s: &'s str) -> Result<(Vec<u8>, &'s str), Diagnostic> {
+    let Some(quote) = s.chars().next().filter(|x| ['\'', '"'].contains(x)) else {
+        return Err(syntax_error(line, s, ".data: expected a string"));
+    };
+    let mut bytes = Vec::new();
+    let mut chars = s.char_indices().skip(1).peekable();
+    while let Some((i, c)) = chars.next() {
+        match c {
+            // `''` in single quotes
+            _ if c == quote && quote == '\'' && chars.peek().map(|x| x.1) == Some('\'') => {
+                chars.next();
+                bytes.push(b'\'');
+            }
+            _ if c == quote => return Ok((bytes, &s[(i + 1)..])),
+            '\\' => {
+                let escape_end =
+                    |n: usize| s[i..].char_indices().nth(n).map_or(s.len(), |x| i + x.0);
+                let byte = match chars.next().map(|x| x.1) {
+                    Some('n') => b'\n',
+                    Some('t') => b'\t',
+                    Some('r') => b'\r',
+                    Some('0') => b'\0',
+                    Some(x @ ('\\' | '\'' | '"')) => x as u8,
+                    Some('x') => {
+                        let hex = s
+                            .get((i + 2)..(i + 4))
+                            .filter(|x| x.bytes().all(|x| x.is_ascii_hexdigit()));
+                        let Some(x) = hex.and_then(|x| u8::from_str_radix(x, 16).ok()) else {
+                            return Err(line.error(
+                                &s[i..escape_end(4)],
+                                DiagnosticKind::InvalidLiteral,
+                                ".data: expected two hex digits after `\\x`",
+                            ));
+                        };
+                        chars.next();
+                        chars.next();
+                        x
+                    }
+                    _ => {
+                        let part = &s[i..escape_end(2)];
+                        return Err(line.error(
+                            part,
+                            DiagnosticKind::InvalidLiteral,
+                            format!(".data: unknown escape: {part}"),
+                        ));
+                    }
+                };
+                bytes.push(byte);
+            }
+            _ => bytes.extend(c.encode_utf8(&mut [0; 4]).as_bytes()),
+        }
+    }
+    Err(syntax_error(line, s, ".data: unterminated string"))
+}
+
+/// Parses the `[a, b, ...]` at the start of `s`. Returns the items and the
+/// rest of `s`.
+fn array<'s>(line: &SourceLine, s: &'s str) -> Result<(Vec<&'s str>, &'s str), Diagnostic> {
+    let mut items = Vec::new();
+    let mut item_start = 1;
+    let mut depth = 0_usize;
+    let mut in_char = false;
+    let mut escaped = false;
+    for (i, c) in s.char_indices().skip(1) {
+        if in_char {
+            match c {
+                _ if escaped => escaped = false,
+                '\\' => escaped = true,
+                '\'' => in_char = false,
+                _ => {}
+            }
+            continue;
+        }
+        match c {
+            '\'' => in_char = true,
+            '(' => depth += 1,
+            ')' => depth = depth.saturating_sub(1),
+            ',' | ']' if depth == 0 => {
+                let item = s[item_start..i].trim();
+                match item {
+                    "" if c == ']' && items.is_empty() => {}
+                    "" => return Err(syntax_error(line, &s[..(i + 1)], ".data: empty array item")),
+                    _ => items.push(item),
+                }
+                if c == ']' {
+                    return Ok((items, &s[(i + 1)..]));
+                }
+                item_start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    Err(syntax_error(line, s, ".data: expected `]`"))
+}
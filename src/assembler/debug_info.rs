@@ -0,0 +1,108 @@
+//! Mapping from program addresses back to the source.
+//!
+//! The sidecar file is a plain tab-separated text file:
+//!
+//! ```text
+//! label<TAB><name><TAB><address>
+//! line<TAB><address><TAB><file><TAB><line><TAB><label><TAB><statement>
+//! ```
+//!
+//! Addresses are written in hex with a `0x` prefix, lines are 1-based, and
+//! `label` in `line` records is the nearest label at or before the address
+//! (empty if there's none).
+
+use crate::parse_u16_literal;
+use anyhow::anyhow;
+use std::collections::BTreeMap;
+use std::fmt::{self, Display, Formatter};
+use std::str::FromStr;
+use yeet_ops::yeet;
+
+/// The file extension of the debug info sidecar file.
+pub const DEBUG_INFO_EXTENSION: &str = "dbg";
+
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub struct DebugInfo {
+    /// Label name to program address mapping.
+    pub labels: BTreeMap<String, u16>,
+    /// Source information of each instruction, ordered by the address.
+    pub lines: Vec<LineInfo>,
+}
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct LineInfo {
+    pub address: u16,
+    pub file: String,
+    /// 1-based line number.
+    pub line: usize,
+    /// The nearest label at or before this instruction.
+    pub label: Option<String>,
+    /// The statement with comments stripped.
+    pub statement: String,
+}
+
+impl DebugInfo {
+    /// Finds the instruction that covers `address`.
+    pub fn find(&self, address: u16) -> Option<&LineInfo> {
+        let index = self
+            .lines
+            .binary_search_by_key(&address, |x| x.address)
+            .ok()?;
+        Some(&self.lines[index])
+    }
+}
+
+impl Display for DebugInfo {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let mut labels = self.labels.iter().collect::<Vec<_>>();
+        labels.sort_by_key(|x| (x.1, x.0));
+        for (name, addr) in labels {
+            writeln!(f, "label\t{name}\t0x{addr:04x}")?;
+        }
+        for x in &self.lines {
+            writeln!(
+                f,
+                "line\t0x{:04x}\t{}\t{}\t{}\t{}",
+                x.address,
+                x.file,
+                x.line,
+                x.label.as_deref().unwrap_or_default(),
+                x.statement.replace('\t', " ")
+            )?;
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for DebugInfo {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut info = DebugInfo::default();
+        for (line_no, line) in s.lines().enumerate() {
+            if line.is_empty() {
+                continue;
+            }
+            let error = || anyhow!("Invalid debug info at line {}: {line}", line_no + 1);
+            let fields = line.split('\t').collect::<Vec<_>>();
+            let address = |x: &str| parse_u16_literal(x).ok_or_else(error);
+            match fields[..] {
+                ["label", name, addr] => {
+                    info.labels.insert(name.into(), address(addr)?);
+                }
+                ["line", addr, file, line, label, statement] => {
+                    info.lines.push(LineInfo {
+                        address: address(addr)?,
+                        file: file.into(),
+                        line: line.parse().map_err(|_| error())?,
+                        label: (!label.is_empty()).then(|| label.into()),
+                        statement: statement.into(),
+                    });
+                }
+                _ => yeet!(error()),
+            }
+        }
+        info.lines.sort_by_key(|x| x.address);
+        Ok(info)
+    }
+}
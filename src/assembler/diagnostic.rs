@@ -0,0 +1,188 @@
+//! Assembler errors pointing into the source.
+
+use std::error::Error;
+use std::fmt::{self, Display, Formatter};
+use std::ops::Range;
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum DiagnosticKind {
+    Syntax,
+    MissingSection,
+    DuplicateSection,
+    /// Two `.data` segments share addresses.
+    Overlap,
+    UnknownOpcode,
+    InvalidOperand,
+    OperandCount,
+    InvalidLiteral,
+    UndefinedSymbol,
+    /// A name defined twice, possibly as different kinds of symbols.
+    DuplicateSymbol,
+    /// A symbol used where its kind doesn't fit, like a data address as a
+    /// jump target.
+    WrongSymbolKind,
+    Overflow,
+    DuplicateMacro,
+    /// Macro expansion nested deeper than [`MAX_EXPANSION_DEPTH`](crate::assembler::macros::MAX_EXPANSION_DEPTH).
+    MacroRecursion,
+    DivisionByZero,
+    IncludeCycle,
+    /// An included file can't be read.
+    IncludeFailed,
+}
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Diagnostic {
+    pub kind: DiagnosticKind,
+    pub message: String,
+    pub file: String,
+    /// `None` if the error doesn't belong to a specific line, like a missing section.
+    pub location: Option<Box<Location>>,
+    /// The macro invocations `location` is expanded from, innermost first.
+    pub expanded_from: Vec<MacroInvocation>,
+}
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct MacroInvocation {
+    pub name: String,
+    /// The file of the call site.
+    pub file: String,
+    /// Points to the macro name at the call site.
+    pub location: Location,
+    /// A built-in pseudo-instruction rather than a `.macro`.
+    pub pseudo: bool,
+}
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Location {
+    /// 1-based line number.
+    pub line: usize,
+    /// 1-based column, counted in chars.
+    pub column: usize,
+    /// Byte range of the offending part in `source_line`.
+    pub span: Range<usize>,
+    pub source_line: String,
+}
+
+impl Location {
+    /// `span` is a byte range in `source_line`.
+    pub fn new(line: usize, source_line: &str, span: Range<usize>) -> Self {
+        Self {
+            line,
+            column: source_line[..span.start].chars().count() + 1,
+            span,
+            source_line: source_line.into(),
+        }
+    }
+
+    /// Writes the `-->` line and the source snippet.
+    fn render(&self, f: &mut Formatter<'_>, file: &str) -> fmt::Result {
+        let gutter = " ".repeat(self.line.to_string().len());
+        writeln!(f, "{gutter}--> {}:{}:{}", file, self.line, self.column)?;
+        writeln!(f, "{gutter} |")?;
+        writeln!(f, "{} | {}", self.line, self.source_line)?;
+        // keep tabs so the carets line up with the source line
+        let indent = self.source_line[..self.span.start]
+            .chars()
+            .map(|x| if x == '\t' { '\t' } else { ' ' })
+            .collect::<String>();
+        let width = self.source_line[self.span.clone()].chars().count().max(1);
+        writeln!(f, "{gutter} | {indent}{}", "^".repeat(width))
+    }
+}
+
+impl Diagnostic {
+    pub fn new(kind: DiagnosticKind, file: impl Into<String>, message: impl Into<String>) -> Self {
+        Self {
+            kind,
+            message: message.into(),
+            file: file.into(),
+            location: None,
+            expanded_from: Vec::new(),
+        }
+    }
+
+    /// Attaches a location. `span` is a byte range in `source_line`.
+    pub fn at(mut self, line: usize, source_line: &str, span: Range<usize>) -> Self {
+        self.location = Some(Box::new(Location::new(line, source_line, span)));
+        self
+    }
+
+    /// Appends an outer macro or pseudo-instruction invocation. `span` points
+    /// to the name in `source_line`.
+    pub fn expanded_from(
+        mut self,
+        name: impl Into<String>,
+        pseudo: bool,
+        file: impl Into<String>,
+        line: usize,
+        source_line: &str,
+        span: Range<usize>,
+    ) -> Self {
+        self.expanded_from.push(MacroInvocation {
+            name: name.into(),
+            file: file.into(),
+            location: Location::new(line, source_line, span),
+            pseudo,
+        });
+        self
+    }
+}
+
+impl Display for Diagnostic {
+    /// Renders in rustc style:
+    ///
+    /// ```text
+    /// error: Unknown opcode: ad
+    ///   --> prog.asm:12:5
+    ///    |
+    /// 12 |     ad r0 r1 r2
+    ///    |     ^^
+    /// ```
+    ///
+    /// followed by a note for each macro or pseudo-instruction invocation the
+    /// line is expanded from.
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        writeln!(f, "error: {}", self.message)?;
+        let Some(location) = &self.location else {
+            return writeln!(f, " --> {}", self.file);
+        };
+        location.render(f, &self.file)?;
+        for x in &self.expanded_from {
+            let kind = if x.pseudo {
+                "pseudo-instruction"
+            } else {
+                "macro"
+            };
+            writeln!(f, "note: in this expansion of {kind} `{}`", x.name)?;
+            x.location.render(f, &x.file)?;
+        }
+        Ok(())
+    }
+}
+
+impl Error for Diagnostic {}
+
+/// All errors found in one assembling pass.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Diagnostics(pub Vec<Diagnostic>);
+
+impl Display for Diagnostics {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        for x in &self.0 {
+            writeln!(f, "{x}")?;
+        }
+        match self.0.len() {
+            1 => write!(f, "error: aborting due to 1 previous error"),
+            n => write!(f, "error: aborting due to {n} previous errors"),
+        }
+    }
+}
+
+impl Error for Diagnostics {}
+
+impl From<Diagnostic> for Diagnostics {
+    fn from(value: Diagnostic) -> Self {
+        Self(vec![value])
+    }
+}
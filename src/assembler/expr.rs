@@ -0,0 +1,497 @@
+//! Constant expressions in operands, `.consts` values and `.data` addresses.
+//!
+//! ```text
+//! expr    = product (op product)*      ; C precedence: `* / %`, `+ -`, `<< >>`, `&`, `^`, `|`
+//! unary   = ("-" | "~" | "+") unary | primary
+//! primary = number | 'c' | name | anonymous | lo(expr) | hi(expr) | (expr)
+//! ```
+//!
+//! Numbers are written like `12`, `0x0c` or `0b1100`, and characters like `'A'`
+//! or `'\n'`. A name is a symbol, and `.name` a local label; an anonymous label
+//! is referred to like `1f` or `1b`. Labels can only be used where the addresses
+//! are known.
+//! `lo` and `hi` take the low and high byte of a 16-bit value. Everything is
+//! computed in 64 bits; only the result has to fit in the operand.
+
+use crate::assembler::diagnostic::DiagnosticKind;
+use crate::assembler::symbol::{self, Symbol, SymbolKind, SymbolTable};
+use std::ops::Range;
+use yeet_ops::yeet;
+
+/// Names an expression can refer to.
+pub(super) struct Scope<'a> {
+    pub(super) symbols: &'a SymbolTable,
+    pub(super) usage: Usage,
+    /// `None` outside `.code`.
+    pub(super) position: Option<Position<'a>>,
+}
+
+/// Where in the code an expression is, to resolve local and anonymous labels.
+#[derive(Debug, Copy, Clone)]
+pub(super) struct Position<'a> {
+    /// The global label the local labels belong to.
+    pub(super) scope: Option<&'a str>,
+    /// Program address of the instruction.
+    pub(super) address: u16,
+}
+
+impl Scope<'_> {
+    fn lookup(&self, name: &str) -> Option<Symbol> {
+        let scope = self.position.and_then(|x| x.scope);
+        self.symbols.get(&symbol::qualify(name, scope)?)
+    }
+}
+
+/// What an expression is evaluated for.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub(super) enum Usage {
+    /// `.consts` and `.data`, where the labels are not defined yet.
+    Const,
+    /// An 8-bit operand.
+    Byte,
+    /// The 16-bit target of `jamv` and `call`. Data symbols are refused.
+    CodeAddress,
+    /// A 16-bit `.data` word.
+    Word,
+}
+
+#[derive(Debug)]
+pub(super) struct ExprError<'a> {
+    /// The offending part of the expression.
+    pub(super) part: &'a str,
+    pub(super) kind: DiagnosticKind,
+    pub(super) message: String,
+}
+
+/// Binary operators by precedence, from the loosest.
+const BINARY_OPERATORS: [&[&str]; 6] = [
+    &["|"],
+    &["^"],
+    &["&"],
+    &["<<", ">>"],
+    &["+", "-"],
+    &["*", "/", "%"],
+];
+
+/// Evaluates `expr` to a byte. Negative results down to -128 are taken as
+/// two's complement.
+pub(super) fn evaluate_u8<'a>(expr: &'a str, scope: &Scope) -> Result<u8, ExprError<'a>> {
+    let value = evaluate(expr, scope)?;
+    if !(-0x80..=0xff).contains(&value) {
+        yeet!(range_error(expr, value, "a byte", scope));
+    }
+    Ok(value as u8)
+}
+
+/// Evaluates `expr` to a 16-bit value. Negative results down to -32768 are
+/// taken as two's complement.
+pub(super) fn evaluate_u16<'a>(expr: &'a str, scope: &Scope) -> Result<u16, ExprError<'a>> {
+    let value = evaluate(expr, scope)?;
+    if !(-0x8000..=0xffff).contains(&value) {
+        yeet!(range_error(expr, value, "16 bits", scope));
+    }
+    Ok(value as u16)
+}
+
+/// A plain literal out of range is reported as an invalid literal, and a plain
+/// label as a misused symbol.
+fn range_error<'a>(expr: &'a str, value: i64, target: &str, scope: &Scope) -> ExprError<'a> {
+    let label_error = |name: &str| ExprError {
+        part: expr,
+        kind: DiagnosticKind::WrongSymbolKind,
+        message: format!(
+            "Label `{name}` is at {value:#06x}, which doesn't fit in {target}; use lo() or hi()"
+        ),
+    };
+    match tokenize(expr).as_deref() {
+        Ok([(Token::Number(_), _)]) => ExprError {
+            part: expr,
+            kind: DiagnosticKind::InvalidLiteral,
+            message: format!("Invalid literal for {target}: {expr}"),
+        },
+        Ok([(Token::Anonymous(name), _)]) => label_error(name),
+        Ok([(Token::Name(name), _)])
+            if scope.lookup(name).map(|x| x.kind) == Some(SymbolKind::Label) =>
+        {
+            label_error(name)
+        }
+        _ => ExprError {
+            part: expr,
+            kind: DiagnosticKind::Overflow,
+            message: format!("`{expr}` is {value}, which doesn't fit in {target}"),
+        },
+    }
+}
+
+pub(super) fn evaluate<'a>(expr: &'a str, scope: &Scope) -> Result<i64, ExprError<'a>> {
+    let mut parser = Parser {
+        expr,
+        tokens: tokenize(expr)?,
+        position: 0,
+        scope,
+    };
+    let (value, _) = parser.binary(0)?;
+    if parser.position != parser.tokens.len() {
+        yeet!(syntax_error(
+            expr,
+            parser.span(),
+            "Unexpected token in expression"
+        ));
+    }
+    Ok(value)
+}
+
+/// Splits a statement at whitespace, except inside parentheses and
+/// character literals, so operands can be expressions with spaces.
+pub(super) fn split_operands(statement: &str) -> Vec<&str> {
+    let mut parts = Vec::new();
+    let mut start = None;
+    let mut depth = 0_usize;
+    let mut in_char = false;
+    let mut escaped = false;
+    for (i, c) in statement.char_indices() {
+        if in_char {
+            match c {
+                _ if escaped => escaped = false,
+                '\\' => escaped = true,
+                '\'' => in_char = false,
+                _ => {}
+            }
+            continue;
+        }
+        match c {
+            '(' => depth += 1,
+            ')' => depth = depth.saturating_sub(1),
+            '\'' => in_char = true,
+            _ if c.is_whitespace() && depth == 0 => {
+                if let Some(s) = start.take() {
+                    parts.push(&statement[s..i]);
+                }
+                continue;
+            }
+            _ => {}
+        }
+        start.get_or_insert(i);
+    }
+    if let Some(s) = start {
+        parts.push(&statement[s..]);
+    }
+    parts
+}
+
+/// Splits off the first operand of `s`, like [`split_operands`]. Returns it
+/// and the rest.
+pub(super) fn split_first_operand(s: &str) -> (&str, &str) {
+    match split_operands(s).first() {
+        Some(&x) => {
+            let end = x.as_ptr() as usize - s.as_ptr() as usize + x.len();
+            (x, s[end..].trim_start())
+        }
+        None => (&s[s.len()..], ""),
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+enum Token<'a> {
+    Number(i64),
+    Name(&'a str),
+    /// A reference to an anonymous label, like `1f`.
+    Anonymous(&'a str),
+    /// Operators and parentheses.
+    Punct(&'a str),
+}
+
+fn syntax_error<'a>(expr: &'a str, span: Range<usize>, message: &str) -> ExprError<'a> {
+    let part = &expr[span];
+    ExprError {
+        part,
+        kind: DiagnosticKind::Syntax,
+        message: if part.is_empty() {
+            format!("{message}: unexpected end")
+        } else {
+            format!("{message}: `{part}`")
+        },
+    }
+}
+
+fn tokenize(expr: &str) -> Result<Vec<(Token<'_>, Range<usize>)>, ExprError<'_>> {
+    let mut tokens = Vec::new();
+    let bytes = expr.as_bytes();
+    let mut i = 0;
+    while i < bytes.len() {
+        let start = i;
+        let rest = &expr[i..];
+        let c = rest.chars().next().unwrap();
+        if c.is_whitespace() {
+            i += c.len_utf8();
+            continue;
+        }
+        let token = if c.is_ascii_digit() {
+            let len = rest
+                .find(|x: char| !x.is_ascii_alphanumeric() && x != '_')
+                .unwrap_or(rest.len());
+            i += len;
+            let literal = &expr[start..i];
+            if let Some(number) = literal.strip_suffix(['f', 'b'])
+                && symbol::is_anonymous(number)
+            {
+                tokens.push((Token::Anonymous(literal), start..i));
+                continue;
+            }
+            let digits = literal.replace('_', "");
+            let value = if let Some(x) = digits.strip_prefix("0x") {
+                i64::from_str_radix(x, 16)
+            } else if let Some(x) = digits.strip_prefix("0b") {
+                i64::from_str_radix(x, 2)
+            } else {
+                digits.parse()
+            };
+            let Ok(value) = value else {
+                yeet!(ExprError {
+                    part: literal,
+                    kind: DiagnosticKind::InvalidLiteral,
+                    message: format!("Invalid number: {literal}"),
+                })
+            };
+            Token::Number(value)
+        } else if c == '\'' {
+            let Some((value, len)) = parse_char_literal(rest) else {
+                yeet!(syntax_error(
+                    expr,
+                    start..expr.len(),
+                    "Invalid character literal"
+                ));
+            };
+            i += len;
+            Token::Number(value as i64)
+        } else if c.is_alphabetic()
+            || c == '_'
+            || (c == '.' && rest[1..].starts_with(|x: char| x.is_alphabetic() || x == '_'))
+        {
+            let len = rest
+                .find(|x: char| !x.is_alphanumeric() && x != '_' && x != '.')
+                .unwrap_or(rest.len());
+            i += len;
+            Token::Name(&expr[start..i])
+        } else {
+            let len = match rest.get(..2) {
+                Some("<<" | ">>") => 2,
+                _ if "+-*/%&|^~()".contains(c) => 1,
+                _ => yeet!(syntax_error(
+                    expr,
+                    start..(start + c.len_utf8()),
+                    "Unexpected character in expression",
+                )),
+            };
+            i += len;
+            Token::Punct(&expr[start..i])
+        };
+        tokens.push((token, start..i));
+    }
+    Ok(tokens)
+}
+
+/// Parses a character literal at the start of `s`. Returns the value and
+/// the length of the literal.
+fn parse_char_literal(s: &str) -> Option<(u32, usize)> {
+    let mut chars = s.char_indices().skip(1);
+    let value = match chars.next()? {
+        (_, '\\') => match chars.next()?.1 {
+            'n' => '\n',
+            't' => '\t',
+            'r' => '\r',
+            '0' => '\0',
+            x @ ('\\' | '\'') => x,
+            _ => return None,
+        },
+        (_, '\'') => return None,
+        (_, x) => x,
+    };
+    let (end, '\'') = chars.next()? else {
+        return None;
+    };
+    Some((value as u32, end + 1))
+}
+
+struct Parser<'a, 's> {
+    expr: &'a str,
+    tokens: Vec<(Token<'a>, Range<usize>)>,
+    position: usize,
+    scope: &'s Scope<'s>,
+}
+
+impl<'a> Parser<'a, '_> {
+    fn peek(&self) -> Option<Token<'a>> {
+        self.tokens.get(self.position).map(|x| x.0)
+    }
+
+    /// Span of the current token, or the empty span at the end.
+    fn span(&self) -> Range<usize> {
+        match self.tokens.get(self.position) {
+            Some((_, x)) => x.clone(),
+            None => self.expr.len()..self.expr.len(),
+        }
+    }
+
+    fn expect(&mut self, punct: &str) -> Result<(), ExprError<'a>> {
+        if self.peek() != Some(Token::Punct(punct)) {
+            yeet!(syntax_error(
+                self.expr,
+                self.span(),
+                &format!("Expected `{punct}`")
+            ));
+        }
+        self.position += 1;
+        Ok(())
+    }
+
+    /// Parses the operators of `level` in [`BINARY_OPERATORS`] and tighter ones.
+    /// Returns the value and its span.
+    fn binary(&mut self, level: usize) -> Result<(i64, Range<usize>), ExprError<'a>> {
+        if level == BINARY_OPERATORS.len() {
+            return self.unary();
+        }
+        let (mut value, mut span) = self.binary(level + 1)?;
+        while let Some(Token::Punct(op)) = self.peek() {
+            if !BINARY_OPERATORS[level].contains(&op) {
+                break;
+            }
+            self.position += 1;
+            let (rhs, rhs_span) = self.binary(level + 1)?;
+            span = span.start..rhs_span.end;
+            let result = match op {
+                "|" => Some(value | rhs),
+                "^" => Some(value ^ rhs),
+                "&" => Some(value & rhs),
+                "<<" => u32::try_from(rhs)
+                    .ok()
+                    .and_then(|x| value.checked_shl(x))
+                    .filter(|x| x >> rhs == value),
+                ">>" => u32::try_from(rhs).ok().and_then(|x| value.checked_shr(x)),
+                "+" => value.checked_add(rhs),
+                "-" => value.checked_sub(rhs),
+                "*" => value.checked_mul(rhs),
+                "/" | "%" if rhs == 0 => yeet!(ExprError {
+                    part: &self.expr[span],
+                    kind: DiagnosticKind::DivisionByZero,
+                    message: "Division by zero".into(),
+                }),
+                "/" => value.checked_div(rhs),
+                _ => value.checked_rem(rhs),
+            };
+            let Some(result) = result else {
+                let part = &self.expr[span];
+                yeet!(ExprError {
+                    part,
+                    kind: DiagnosticKind::Overflow,
+                    message: format!("Arithmetic overflow in `{part}`"),
+                });
+            };
+            value = result;
+        }
+        Ok((value, span))
+    }
+
+    fn unary(&mut self) -> Result<(i64, Range<usize>), ExprError<'a>> {
+        let start = self.span().start;
+        let op = match self.peek() {
+            Some(Token::Punct(x @ ("-" | "~" | "+"))) => x,
+            _ => return self.primary(),
+        };
+        self.position += 1;
+        let (value, span) = self.unary()?;
+        let value = match op {
+            "-" => value.wrapping_neg(),
+            "~" => !value,
+            _ => value,
+        };
+        Ok((value, start..span.end))
+    }
+
+    fn primary(&mut self) -> Result<(i64, Range<usize>), ExprError<'a>> {
+        let span = self.span();
+        let Some(token) = self.peek() else {
+            yeet!(syntax_error(self.expr, span, "Expected a value"));
+        };
+        self.position += 1;
+        match token {
+            Token::Number(x) => Ok((x, span)),
+            Token::Punct("(") => {
+                let (value, _) = self.binary(0)?;
+                let end = self.span().end;
+                self.expect(")")?;
+                Ok((value, span.start..end))
+            }
+            Token::Name(name @ ("lo" | "hi")) if self.peek() == Some(Token::Punct("(")) => {
+                self.position += 1;
+                let (value, arg_span) = self.binary(0)?;
+                let end = self.span().end;
+                self.expect(")")?;
+                if !(0..=0xffff).contains(&value) {
+                    let part = &self.expr[arg_span];
+                    yeet!(ExprError {
+                        part,
+                        kind: DiagnosticKind::Overflow,
+                        message: format!("`{part}` is {value}, which doesn't fit in 16 bits"),
+                    });
+                }
+                let byte = if name == "lo" {
+                    value & 0xff
+                } else {
+                    value >> 8
+                };
+                Ok((byte, span.start..end))
+            }
+            Token::Anonymous(reference) => {
+                let part = &self.expr[span.clone()];
+                let (number, direction) = reference.split_at(reference.len() - 1);
+                let forward = direction == "f";
+                let Some(position) = self.scope.position else {
+                    yeet!(ExprError {
+                        part,
+                        kind: DiagnosticKind::UndefinedSymbol,
+                        message: format!("Anonymous labels can only be used in code: {reference}"),
+                    });
+                };
+                let Some(address) =
+                    self.scope
+                        .symbols
+                        .find_anonymous(number, position.address, forward)
+                else {
+                    yeet!(ExprError {
+                        part,
+                        kind: DiagnosticKind::UndefinedSymbol,
+                        message: format!(
+                            "No anonymous label `{number}` {} this instruction",
+                            if forward { "after" } else { "before" }
+                        ),
+                    });
+                };
+                Ok((address as i64, span))
+            }
+            Token::Name(name) => {
+                let part = &self.expr[span.clone()];
+                let Some(symbol) = self.scope.lookup(name) else {
+                    yeet!(ExprError {
+                        part,
+                        kind: DiagnosticKind::UndefinedSymbol,
+                        message: match self.scope.usage {
+                            Usage::Const => format!("Undefined constant: {name}"),
+                            Usage::Byte | Usage::Word => format!("Undefined symbol: {name}"),
+                            Usage::CodeAddress => format!("Label not found: {name}"),
+                        },
+                    });
+                };
+                if self.scope.usage == Usage::CodeAddress && symbol.kind == SymbolKind::Data {
+                    yeet!(ExprError {
+                        part,
+                        kind: DiagnosticKind::WrongSymbolKind,
+                        message: format!("`{name}` is a data symbol, not a code address"),
+                    });
+                }
+                Ok((symbol.value as i64, span))
+            }
+            Token::Punct(_) => Err(syntax_error(self.expr, span, "Expected a value")),
+        }
+    }
+}
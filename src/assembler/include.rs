@@ -0,0 +1,142 @@
+//! `.include <path>` resolution.
+//!
+//! Paths are relative to the including file. The sections of an included file
+//! are appended to the same-named ones of the including file, so `.consts`,
+//! `.data` and `.code` can be spread over several files. `.data` sections are
+//! merged by segment name, and must have the same `mem_start`. A file is only
+//! included once, and an include cycle is an error.
+
+use crate::assembler::diagnostic::{Diagnostic, DiagnosticKind};
+use crate::assembler::Sections;
+use std::collections::{HashMap, HashSet};
+use std::io;
+use std::path::{Component, Path, PathBuf};
+
+/// Reads the files of `.include` directives.
+pub trait SourceLoader {
+    fn load(&self, path: &Path) -> io::Result<String>;
+}
+
+/// Reads from the file system.
+#[derive(Debug, Copy, Clone, Default)]
+pub struct FileSystem;
+
+impl SourceLoader for FileSystem {
+    fn load(&self, path: &Path) -> io::Result<String> {
+        std::fs::read_to_string(path)
+    }
+}
+
+/// In-memory files.
+impl SourceLoader for HashMap<PathBuf, String> {
+    fn load(&self, path: &Path) -> io::Result<String> {
+        self.get(path)
+            .cloned()
+            .ok_or(io::ErrorKind::NotFound.into())
+    }
+}
+
+struct Includes<'a> {
+    loader: &'a dyn SourceLoader,
+    /// The files being included, from the root.
+    stack: Vec<PathBuf>,
+    loaded: HashSet<PathBuf>,
+}
+
+/// Parses `code` and all the files it includes.
+pub(super) fn load_sections(
+    code: &str,
+    file: &str,
+    loader: &dyn SourceLoader,
+    diagnostics: &mut Vec<Diagnostic>,
+) -> Sections {
+    let path = normalize(Path::new(file));
+    let mut includes = Includes {
+        loader,
+        stack: vec![path.clone()],
+        loaded: HashSet::from([path]),
+    };
+    includes.load(code, file, diagnostics)
+}
+
+impl Includes<'_> {
+    fn load(&mut self, code: &str, file: &str, diagnostics: &mut Vec<Diagnostic>) -> Sections {
+        let mut sections = Sections::new(code, file, diagnostics);
+        let base = Path::new(file).parent().unwrap_or(Path::new(""));
+        for include in std::mem::take(&mut sections.includes) {
+            let title = &include.title;
+            let [arg] = include.args()[..] else {
+                diagnostics.push(title.error(
+                    title.text.trim_end(),
+                    DiagnosticKind::Syntax,
+                    ".include: expected one path",
+                ));
+                continue;
+            };
+            let path = normalize(&base.join(unquote(arg)));
+
+            if let Some(start) = self.stack.iter().position(|x| *x == path) {
+                let chain = self.stack[start..]
+                    .iter()
+                    .chain([&path])
+                    .map(|x| x.display().to_string())
+                    .collect::<Vec<_>>();
+                diagnostics.push(title.error(
+                    arg,
+                    DiagnosticKind::IncludeCycle,
+                    format!("Include cycle: {}", chain.join(" -> ")),
+                ));
+                continue;
+            }
+            if !self.loaded.insert(path.clone()) {
+                continue;
+            }
+            let code = match self.loader.load(&path) {
+                Ok(x) => x,
+                Err(e) => {
+                    diagnostics.push(title.error(
+                        arg,
+                        DiagnosticKind::IncludeFailed,
+                        format!("Cannot read {}: {e}", path.display()),
+                    ));
+                    continue;
+                }
+            };
+
+            self.stack.push(path.clone());
+            let included = self.load(&code, &path.display().to_string(), diagnostics);
+            self.stack.pop();
+            sections.merge(included, diagnostics);
+        }
+        sections
+    }
+}
+
+/// Strips the quotes of `"path"` or `'path'`.
+fn unquote(s: &str) -> &str {
+    ['"', '\'']
+        .iter()
+        .find_map(|&q| s.strip_prefix(q)?.strip_suffix(q))
+        .unwrap_or(s)
+}
+
+/// Removes `.` and resolves `..` components lexically, so one file always gets
+/// the same path.
+fn normalize(path: &Path) -> PathBuf {
+    let mut normalized = PathBuf::new();
+    for x in path.components() {
+        match x {
+            Component::CurDir => {}
+            Component::ParentDir
+                if matches!(
+                    normalized.components().next_back(),
+                    Some(Component::Normal(_))
+                ) =>
+            {
+                normalized.pop();
+            }
+            x => normalized.push(x),
+        }
+    }
+    normalized
+}
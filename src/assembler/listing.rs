@@ -0,0 +1,138 @@
+//! The listing: the program bytes next to the source, followed by the symbol
+//! table and a map of the RAM occupied by `.data`.
+//!
+//! ```text
+//! ADDR  BYTES        CYC  SOURCE
+//! 0000  01 02 10 06       ; header v1
+//! 0004  68 69             ; data at 0x10
+//! 0006                    main.asm:6  main:
+//! 0006  83 68 00 0c  1    main.asm:7  cp 'h' out
+//! ```
+//!
+//! Every instruction takes one cycle.
+
+use crate::assembler::symbol::{SymbolKind, SymbolTable};
+use crate::assembler::INST_LENGTH;
+use crate::header::{Header, HeaderVersion};
+use std::fmt::Write;
+
+/// Bytes shown in one row. Longer ones continue in the following rows.
+const ROW_BYTES: usize = INST_LENGTH as usize;
+
+/// A line of the code section.
+#[derive(Debug, Clone)]
+pub(super) struct Row {
+    pub(super) address: u16,
+    /// Empty for labels and comments.
+    pub(super) bytes: Vec<u8>,
+    pub(super) file: String,
+    pub(super) line: usize,
+    /// The source text, with the invocations it comes from.
+    pub(super) source: String,
+}
+
+/// `binary_header` is the encoded `header`.
+pub(super) fn render(
+    rows: &[Row],
+    header: &Header,
+    binary_header: &[u8],
+    symbols: &SymbolTable,
+) -> String {
+    let mut listing = String::new();
+    let out = &mut listing;
+    writeln!(out, "ADDR  BYTES        CYC  SOURCE").unwrap();
+
+    let version = match header.version {
+        HeaderVersion::V1 => "header v1",
+        HeaderVersion::V2 => "header v2",
+    };
+    let data_len = header.segments.iter().map(|x| x.data.len()).sum::<usize>();
+    let (fields, mut data) = binary_header.split_at(binary_header.len() - data_len);
+    write_bytes(out, 0, fields, None, &format!("; {version}"));
+    let mut address = fields.len();
+    for x in &header.segments {
+        let (bytes, rest) = data.split_at(x.data.len());
+        let comment = format!("; data at 0x{:02x}", x.mem_start);
+        write_bytes(out, address as u16, bytes, None, &comment);
+        address += bytes.len();
+        data = rest;
+    }
+    for x in rows {
+        let source = format!("{}:{}  {}", x.file, x.line, x.source);
+        let cycles = (!x.bytes.is_empty()).then_some(1);
+        write_bytes(out, x.address, &x.bytes, cycles, &source);
+    }
+
+    writeln!(out, "\nSYMBOL{:<26}KIND      VALUE   SIZE", "").unwrap();
+    let mut sorted = symbols.iter().collect::<Vec<_>>();
+    sorted.sort_by_key(|&(name, x)| (kind_order(x.kind), x.value, name));
+    for (name, x) in sorted {
+        let (kind, value) = match x.kind {
+            SymbolKind::Const => ("const", format!("{}", x.value)),
+            SymbolKind::Data => ("data", format!("0x{:02x}", x.value)),
+            SymbolKind::Label => ("label", format!("0x{:04x}", x.value)),
+        };
+        let size = symbols
+            .size(name)
+            .map(|x| x.to_string())
+            .unwrap_or_default();
+        let row = format!("{name:<32}{kind:<10}{value:<8}{size}");
+        writeln!(out, "{}", row.trim_end()).unwrap();
+    }
+
+    writeln!(out, "\nRAM   {}", ram_map_header()).unwrap();
+    let mut used = [false; 256];
+    for x in &header.segments {
+        let start = x.mem_start as usize;
+        let end = (start + x.data.len()).min(used.len());
+        used[start..end].fill(true);
+    }
+    for (i, row) in used.chunks(16).enumerate() {
+        let cells = row
+            .iter()
+            .map(|&x| if x { "#" } else { "." })
+            .collect::<Vec<_>>();
+        writeln!(out, "{:02x}    {}", i * 16, cells.join(" ")).unwrap();
+    }
+    let count = used.iter().filter(|&&x| x).count();
+    writeln!(out, "{count} of {} bytes used", used.len()).unwrap();
+    listing
+}
+
+/// Writes `bytes` in rows starting at `address`. `source` goes with the first
+/// row.
+fn write_bytes(out: &mut String, address: u16, bytes: &[u8], cycles: Option<u8>, source: &str) {
+    let cycles = cycles.map(|x| x.to_string()).unwrap_or_default();
+    if bytes.is_empty() {
+        writeln!(out, "{address:04x}  {:<11}  {cycles:<3}  {source}", "").unwrap();
+        return;
+    }
+    for (i, chunk) in bytes.chunks(ROW_BYTES).enumerate() {
+        let hex = chunk
+            .iter()
+            .map(|x| format!("{x:02x}"))
+            .collect::<Vec<_>>()
+            .join(" ");
+        let address = address as usize + i * ROW_BYTES;
+        if i == 0 {
+            writeln!(out, "{address:04x}  {hex:<11}  {cycles:<3}  {source}").unwrap();
+        } else {
+            writeln!(out, "{address:04x}  {hex}").unwrap();
+        }
+    }
+}
+
+fn ram_map_header() -> String {
+    (0..16)
+        .map(|x| format!("{x:x}"))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+fn kind_order(kind: SymbolKind) -> u8 {
+    match kind {
+        SymbolKind::Label => 0,
+        SymbolKind::Data => 1,
+        SymbolKind::Const => 2,
+    }
+}
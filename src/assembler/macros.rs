@@ -0,0 +1,243 @@
+//! `.macro name params... .endm` definitions and their expansion in `.code`.
+//!
+//! In a macro body, `\param` is replaced by the argument text, and the labels
+//! defined in the body are renamed per expansion, so a macro can be invoked
+//! several times without label clashes. The renamed labels have a `.`, so they
+//! don't end the scope of the local labels around the invocation. Anonymous
+//! labels are left as they are.
+
+use crate::assembler::diagnostic::{Diagnostic, DiagnosticKind};
+use crate::assembler::{expr, symbol};
+use crate::assembler::{Assembler, Section, SourceLine};
+use crate::instruction::Opcode;
+use regex::Regex;
+use std::collections::{HashMap, HashSet};
+use std::rc::Rc;
+use std::str::FromStr;
+use std::sync::LazyLock;
+
+/// Invocations nested deeper than this are rejected. As macros have no
+/// conditionals, this is only hit by a recursive macro.
+pub const MAX_EXPANSION_DEPTH: usize = 64;
+
+/// `\param` in a macro body.
+static PARAM: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"\\(\w+)").unwrap());
+/// A quoted character or string, which is left as it is, or a word that may be
+/// a label name, also inside an expression like `lo(label)` or `label+1`.
+static TOKEN: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r#"'(?:[^'\\]|\\.)*'|"(?:[^"\\]|\\.)*"|[\w.\\]+"#).unwrap());
+
+/// A macro or pseudo-instruction invocation some expanded lines come from.
+#[derive(Debug)]
+pub(super) struct Expansion {
+    pub(super) name: String,
+    pub(super) call_site: SourceLine,
+    /// A built-in pseudo-instruction rather than a `.macro`.
+    pub(super) pseudo: bool,
+}
+
+#[derive(Debug)]
+struct Macro {
+    params: Vec<String>,
+    body: Vec<SourceLine>,
+    /// Labels defined in the body.
+    labels: HashSet<String>,
+}
+
+#[derive(Debug)]
+pub(super) struct Macros {
+    macros: HashMap<String, Macro>,
+    /// Number of expansions so far, used to make the local labels unique.
+    expansion_count: usize,
+}
+
+/// Hit [`MAX_EXPANSION_DEPTH`].
+struct TooDeep;
+
+impl Macros {
+    pub(super) fn new(definitions: &[Section], diagnostics: &mut Vec<Diagnostic>) -> Self {
+        let mut macros = HashMap::new();
+        for definition in definitions {
+            let title = &definition.title;
+            let args = definition.args();
+            let Some((&name, params)) = args.split_first() else {
+                diagnostics.push(title.error(
+                    title.text.trim(),
+                    DiagnosticKind::Syntax,
+                    ".macro: missing macro name",
+                ));
+                continue;
+            };
+            if Opcode::from_str(name).is_ok() || name.ends_with(':') {
+                diagnostics.push(title.error(
+                    name,
+                    DiagnosticKind::Syntax,
+                    format!("Invalid macro name: {name}"),
+                ));
+                continue;
+            }
+            if macros.contains_key(name) {
+                diagnostics.push(title.error(
+                    name,
+                    DiagnosticKind::DuplicateMacro,
+                    format!("Macro redefined: {name}"),
+                ));
+                continue;
+            }
+            if let Some(&x) = params
+                .iter()
+                .enumerate()
+                .find(|(i, x)| params[..*i].contains(x))
+                .map(|x| x.1)
+            {
+                diagnostics.push(title.error(
+                    x,
+                    DiagnosticKind::Syntax,
+                    format!("Duplicated macro parameter: {x}"),
+                ));
+                continue;
+            }
+
+            let mut labels = HashSet::new();
+            for line in &definition.body_lines {
+                let statement = Assembler::remove_comment(line.text.trim());
+                if let Some(x) = statement.strip_suffix(':')
+                    && !symbol::is_anonymous(x)
+                {
+                    labels.insert(x.to_string());
+                }
+                for x in PARAM.find_iter(statement) {
+                    if !params.contains(&&x.as_str()[1..]) {
+                        diagnostics.push(line.error(
+                            &statement[x.range()],
+                            DiagnosticKind::UndefinedSymbol,
+                            format!("Unknown macro parameter: {}", x.as_str()),
+                        ));
+                    }
+                }
+            }
+            macros.insert(
+                name.to_string(),
+                Macro {
+                    params: params.iter().map(|&x| x.into()).collect(),
+                    body: definition.body_lines.clone(),
+                    labels,
+                },
+            );
+        }
+        Self {
+            macros,
+            expansion_count: 0,
+        }
+    }
+
+    /// Expands all the macro invocations in `lines`. Erroneous invocations are
+    /// reported and dropped.
+    pub(super) fn expand(
+        &mut self,
+        lines: &[SourceLine],
+        diagnostics: &mut Vec<Diagnostic>,
+    ) -> Vec<SourceLine> {
+        let mut expanded = Vec::new();
+        for line in lines {
+            let mut output = Vec::new();
+            match self.expand_line(line, 0, diagnostics, &mut output) {
+                Ok(()) => expanded.extend(output),
+                Err(TooDeep) => {
+                    let statement = Assembler::remove_comment(line.text.trim());
+                    let name = statement.split_whitespace().next().unwrap();
+                    diagnostics.push(line.error(name,
+                        DiagnosticKind::MacroRecursion,
+                        format!(
+                            "Recursion limit ({MAX_EXPANSION_DEPTH}) reached while expanding macro `{name}`"
+                        ),
+                    ));
+                }
+            }
+        }
+        expanded
+    }
+
+    fn expand_line(
+        &mut self,
+        line: &SourceLine,
+        depth: usize,
+        diagnostics: &mut Vec<Diagnostic>,
+        output: &mut Vec<SourceLine>,
+    ) -> Result<(), TooDeep> {
+        let statement = Assembler::remove_comment(line.text.trim());
+        let split = expr::split_operands(statement);
+        let mut split = split.into_iter();
+        let Some((name, m)) = split.next().and_then(|x| Some((x, self.macros.get(x)?))) else {
+            output.push(line.clone());
+            return Ok(());
+        };
+        let args = split.collect::<Vec<_>>();
+        if args.len() != m.params.len() {
+            let span = match args.get(m.params.len()) {
+                // point to the first redundant argument
+                Some(&x) => x,
+                None => statement,
+            };
+            diagnostics.push(line.error(
+                span,
+                DiagnosticKind::OperandCount,
+                format!(
+                    "Macro `{name}` takes {} argument(s) but {} given",
+                    m.params.len(),
+                    args.len()
+                ),
+            ));
+            return Ok(());
+        }
+        if depth == MAX_EXPANSION_DEPTH {
+            return Err(TooDeep);
+        }
+
+        let expansion = Rc::new(Expansion {
+            name: name.into(),
+            call_site: line.clone(),
+            pseudo: false,
+        });
+        let id = self.expansion_count;
+        let body = m
+            .body
+            .iter()
+            .map(|x| SourceLine {
+                file: Rc::clone(&x.file),
+                line: x.line,
+                text: m.substitute(&x.text, name, id, &args),
+                expansion: Some(Rc::clone(&expansion)),
+            })
+            .collect::<Vec<_>>();
+        self.expansion_count += 1;
+        for x in &body {
+            self.expand_line(x, depth + 1, diagnostics, output)?;
+        }
+        Ok(())
+    }
+}
+
+impl Macro {
+    /// Renames the local labels in `text` and substitutes the parameters.
+    /// `id` makes the label names unique.
+    fn substitute(&self, text: &str, name: &str, id: usize, args: &[&str]) -> String {
+        let (code, comment) = text.split_at(Assembler::comment_start(text).unwrap_or(text.len()));
+        let code = TOKEN.replace_all(code, |x: &regex::Captures| {
+            let token = &x[0];
+            if self.labels.contains(token) {
+                format!("{name}.{id}.{}", token.trim_start_matches('.'))
+            } else {
+                token.into()
+            }
+        });
+        let code = PARAM.replace_all(&code, |x: &regex::Captures| {
+            match self.params.iter().position(|p| *p == x[1]) {
+                Some(i) => args[i].to_string(),
+                // already reported
+                None => x[0].to_string(),
+            }
+        });
+        format!("{code}{comment}")
+    }
+}
@@ -0,0 +1,132 @@
+//! Built-in pseudo-instructions, expanded to real instructions after the
+//! macros.
+//!
+//! | Pseudo-instruction    | Expansion                                     |
+//! |-----------------------|-----------------------------------------------|
+//! | `jmp label`           | `jamv label`, `jp`                            |
+//! | `jeq a b label`       | `jamv label`, `jpeq a b`; also `jne`, `jlt`, `jle`, `jgt` and `jge` |
+//! | `mov a b`             | `cp a b`                                      |
+//! | `mov16 value lo hi`   | `cp lo(value) lo`, `cp hi(value) hi`          |
+//! | `inc r`               | `add r 1 r`                                   |
+//! | `dec r`               | `sub r 1 r`                                   |
+//! | `neg r`               | `sub 0 r r`                                   |
+//! | `clr r`               | `cp 0 r`                                      |
+//! | `swap a b`            | `xor a b a`, `xor a b b`, `xor a b a`         |
+//!
+//! A macro of the same name takes precedence.
+
+use crate::assembler::diagnostic::{Diagnostic, DiagnosticKind};
+use crate::assembler::macros::Expansion;
+use crate::assembler::{expr, Assembler, SourceLine};
+use std::rc::Rc;
+
+/// Name, parameters, and the expansion where `\param` is replaced by the
+/// operand.
+const PSEUDO_INSTRUCTIONS: [(&str, &[&str], &[&str]); 14] = [
+    ("jmp", &["target"], &["jamv \\target", "jp"]),
+    (
+        "jeq",
+        &["a", "b", "target"],
+        &["jamv \\target", "jpeq \\a \\b"],
+    ),
+    (
+        "jne",
+        &["a", "b", "target"],
+        &["jamv \\target", "jpne \\a \\b"],
+    ),
+    (
+        "jlt",
+        &["a", "b", "target"],
+        &["jamv \\target", "jplt \\a \\b"],
+    ),
+    (
+        "jle",
+        &["a", "b", "target"],
+        &["jamv \\target", "jple \\a \\b"],
+    ),
+    (
+        "jgt",
+        &["a", "b", "target"],
+        &["jamv \\target", "jpgt \\a \\b"],
+    ),
+    (
+        "jge",
+        &["a", "b", "target"],
+        &["jamv \\target", "jpge \\a \\b"],
+    ),
+    ("mov", &["a", "b"], &["cp \\a \\b"]),
+    (
+        "mov16",
+        &["value", "lo", "hi"],
+        &["cp lo(\\value) \\lo", "cp hi(\\value) \\hi"],
+    ),
+    ("inc", &["r"], &["add \\r 1 \\r"]),
+    ("dec", &["r"], &["sub \\r 1 \\r"]),
+    ("neg", &["r"], &["sub 0 \\r \\r"]),
+    ("clr", &["r"], &["cp 0 \\r"]),
+    // with a and b the same, it's cleared
+    (
+        "swap",
+        &["a", "b"],
+        &["xor \\a \\b \\a", "xor \\a \\b \\b", "xor \\a \\b \\a"],
+    ),
+];
+
+/// Expands the pseudo-instructions in `lines`. Ones with a wrong operand count
+/// are reported and dropped.
+pub(super) fn expand(lines: &[SourceLine], diagnostics: &mut Vec<Diagnostic>) -> Vec<SourceLine> {
+    let mut expanded = Vec::new();
+    for line in lines {
+        let statement = Assembler::remove_comment(line.text.trim());
+        let split = expr::split_operands(statement);
+        let Some(&(name, params, body)) = split
+            .first()
+            // case-insensitive, like the opcodes
+            .and_then(|&x| {
+                PSEUDO_INSTRUCTIONS
+                    .iter()
+                    .find(|p| p.0.eq_ignore_ascii_case(x))
+            })
+        else {
+            expanded.push(line.clone());
+            continue;
+        };
+        let operands = &split[1..];
+        if operands.len() != params.len() {
+            let span = match operands.get(params.len()) {
+                // point to the first redundant operand
+                Some(&x) => x,
+                None => statement,
+            };
+            diagnostics.push(line.error(
+                span,
+                DiagnosticKind::OperandCount,
+                format!(
+                    "`{name}` takes {} operand(s) but {} given",
+                    params.len(),
+                    operands.len()
+                ),
+            ));
+            continue;
+        }
+
+        let expansion = Rc::new(Expansion {
+            name: name.into(),
+            call_site: line.clone(),
+            pseudo: true,
+        });
+        for x in body {
+            let mut text = x.to_string();
+            for (param, operand) in params.iter().zip(operands) {
+                text = text.replace(&format!("\\{param}"), operand);
+            }
+            expanded.push(SourceLine {
+                file: Rc::clone(&line.file),
+                line: line.line,
+                text,
+                expansion: Some(Rc::clone(&expansion)),
+            });
+        }
+    }
+    expanded
+}
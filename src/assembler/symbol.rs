@@ -0,0 +1,131 @@
+//! Names defined in a program: constants, `.data` entries and code labels.
+//!
+//! They share one namespace, so a name can only be defined once. The kind
+//! decides where a symbol can be used: a data address is not a jump target,
+//! and a label beyond `0xff` doesn't fit in an 8-bit operand.
+//!
+//! Labels come in three forms:
+//!
+//! - `name:` is a global label. It opens a scope for the local labels, unless
+//!   the name has a `.`, like the labels renamed in macro expansions.
+//! - `.name:` is local to the preceding global label `outer`, and is defined
+//!   as `outer.name`. In that scope, `.name` refers to it.
+//! - `1:` is an anonymous label, and can be defined many times. `1f` refers to
+//!   the next one after the instruction, and `1b` to the last one at or before it.
+
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::fmt::{self, Display, Formatter};
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum SymbolKind {
+    /// A `.consts` entry, or the length name of a `.data` entry.
+    Const,
+    /// The RAM address of a `.data` entry.
+    Data,
+    /// A code label. Its value is a 16-bit program address.
+    Label,
+}
+
+impl Display for SymbolKind {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            SymbolKind::Const => "constant",
+            SymbolKind::Data => "data symbol",
+            SymbolKind::Label => "label",
+        })
+    }
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct Symbol {
+    pub kind: SymbolKind,
+    pub value: u16,
+}
+
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub struct SymbolTable {
+    symbols: HashMap<String, Symbol>,
+    /// Addresses of the anonymous labels, by their number, in order.
+    anonymous: HashMap<String, Vec<u16>>,
+    /// Sizes of the data symbols in bytes.
+    sizes: HashMap<String, u16>,
+}
+
+impl SymbolTable {
+    pub fn get(&self, name: &str) -> Option<Symbol> {
+        self.symbols.get(name).copied()
+    }
+
+    /// Defines `name`. If it's already defined, returns the existing symbol
+    /// and leaves it unchanged.
+    pub fn define(&mut self, name: &str, kind: SymbolKind, value: u16) -> Result<(), Symbol> {
+        if let Some(&x) = self.symbols.get(name) {
+            return Err(x);
+        }
+        self.symbols.insert(name.into(), Symbol { kind, value });
+        Ok(())
+    }
+
+    /// Defines the anonymous label `number`. Definitions must be in address
+    /// order.
+    pub fn define_anonymous(&mut self, number: &str, address: u16) {
+        self.anonymous
+            .entry(number.into())
+            .or_default()
+            .push(address);
+    }
+
+    /// Finds the anonymous label `number` after `address` if `forward`, or
+    /// else the last one at or before it.
+    pub fn find_anonymous(&self, number: &str, address: u16, forward: bool) -> Option<u16> {
+        let addresses = self.anonymous.get(number)?;
+        let after = addresses.partition_point(|&x| x <= address);
+        if forward {
+            addresses.get(after).copied()
+        } else {
+            after.checked_sub(1).map(|x| addresses[x])
+        }
+    }
+
+    pub fn set_size(&mut self, name: &str, size: u16) {
+        self.sizes.insert(name.into(), size);
+    }
+
+    /// Size of the data symbol `name` in bytes.
+    pub fn size(&self, name: &str) -> Option<u16> {
+        self.sizes.get(name).copied()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, Symbol)> {
+        self.symbols.iter().map(|(k, &v)| (k.as_str(), v))
+    }
+
+    /// Symbols of `kind`, with their values.
+    pub fn of_kind(&self, kind: SymbolKind) -> HashMap<String, u16> {
+        self.iter()
+            .filter(|x| x.1.kind == kind)
+            .map(|(k, v)| (k.into(), v.value))
+            .collect()
+    }
+}
+
+/// Whether the label `name` is anonymous, like `1`.
+pub fn is_anonymous(name: &str) -> bool {
+    !name.is_empty() && name.bytes().all(|x| x.is_ascii_digit())
+}
+
+/// Whether the label `name` opens a scope for local labels.
+pub fn is_scope(name: &str) -> bool {
+    !name.contains('.') && !is_anonymous(name)
+}
+
+/// The full name of the label or symbol `name` written in the scope of the
+/// global label `scope`. `None` for a local name outside any scope.
+pub fn qualify<'a>(name: &'a str, scope: Option<&str>) -> Option<Cow<'a, str>> {
+    if name.starts_with('.') {
+        Some(format!("{}{name}", scope?).into())
+    } else {
+        Some(name.into())
+    }
+}
@@ -5,10 +5,11 @@ fn neg(n: u8) -> u8 {
     (!n).wrapping_add(1)
 }
 
+/// The low and high bytes of the 16-bit product, for `mull` and `mulh`.
 fn u8_multiply(n1: u8, n2: u8) -> (u8, u8) {
     let product = n1 as u16 * n2 as u16;
-    let low = (product & 0b00001111) as u8;
-    let high = (product >> 4) as u8;
+    let low = product as u8;
+    let high = (product >> 8) as u8;
     (low, high)
 }
 
@@ -18,12 +19,12 @@ pub fn alu(opcode: u8, n1: u8, n2: u8) -> AluOutput {
     let mut carry = false;
     let out = match alu_opcode {
         AluOpcode::Add => {
-            let x = n1.carrying_add(n2, false);
+            let x = n1.overflowing_add(n2);
             carry = x.1;
             x.0
         }
         AluOpcode::Sub => {
-            let x = n1.carrying_add(neg(n2), false);
+            let x = n1.overflowing_add(neg(n2));
             carry = x.1;
             x.0
         }
@@ -73,15 +74,17 @@ pub fn jump_condition(opcode: u8, n1: u8, n2: u8) -> bool {
     out
 }
 
+/// `shl` and `shr` shift all the bits out by 8 or more, while the wrapping
+/// variants take the amount modulo 8.
 pub fn shift(opcode: u8, n1: u8, n2: u8) -> u8 {
     match opcode & OPCODE_SUBTYPE_MASK {
         0b000 => {
             // shl
-            n1 << n2
+            n1.checked_shl(n2 as u32).unwrap_or(0)
         }
         0b001 => {
             // shr
-            n1 >> n2
+            n1.checked_shr(n2 as u32).unwrap_or(0)
         }
         0b010 => {
             // wrapping shl
@@ -0,0 +1,308 @@
+//! A step debugger built upon [`Emulator::tick`].
+//!
+//! The debugger itself does no I/O with the user; it takes textual commands
+//! via [`Debugger::execute`] and returns what should be printed, so it can be
+//! driven by a REPL, or by tests.
+
+use crate::assembler::debug_info::DebugInfo;
+use crate::emulator::Emulator;
+use crate::parse_u16_literal;
+use crate::stack::BoundedStack;
+use anyhow::anyhow;
+use std::collections::BTreeSet;
+use std::fmt::{self, Write};
+use std::str::FromStr;
+use yeet_ops::yeet;
+
+/// Register names of the tier1 slots, indexed by the register code.
+const REGISTER_NAMES: [&str; 16] = [
+    "r0", "r1", "r2", "r3", "r4", "r5", "r6", "r7", "r8", "r9", "r10", "r11", "io", "aor", "azr",
+    "fss",
+];
+
+/// How many entries from the top of each stack are shown in a state dump.
+const STACK_DUMP_DEPTH: usize = 8;
+
+pub const HELP: &str = "\
+Commands:
+  s, step [n]           Execute n instructions (default: 1)
+  c, continue           Run until a breakpoint is hit or the CPU halts
+  b, break <loc>        Set a breakpoint at a label name or an address
+  d, delete <loc>       Delete a breakpoint
+  bl, breakpoints       List all breakpoints
+  i, info               Print the CPU state
+  l, labels             List all labels
+  q, quit               Exit the debugger
+  h, help               Print this help
+
+An empty line repeats the last command.";
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum Command {
+    Step(u64),
+    Continue,
+    Break(String),
+    Delete(String),
+    Breakpoints,
+    Info,
+    Labels,
+    Quit,
+    Help,
+}
+
+impl FromStr for Command {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut split = s.split_whitespace();
+        let name = split.next().ok_or(anyhow!("Empty command"))?;
+        let arg = split.next();
+        if split.next().is_some() {
+            yeet!(anyhow!("Too many arguments: {s}"));
+        }
+
+        let location_arg = || {
+            arg.map(String::from)
+                .ok_or(anyhow!("Missing location for `{name}`"))
+        };
+        let command = match name {
+            "s" | "step" => {
+                let count = match arg {
+                    None => 1,
+                    Some(x) => x.parse().map_err(|_| anyhow!("Invalid step count: {x}"))?,
+                };
+                Command::Step(count)
+            }
+            "c" | "continue" => Command::Continue,
+            "b" | "break" => Command::Break(location_arg()?),
+            "d" | "delete" => Command::Delete(location_arg()?),
+            "bl" | "breakpoints" => Command::Breakpoints,
+            "i" | "info" => Command::Info,
+            "l" | "labels" => Command::Labels,
+            "q" | "quit" => Command::Quit,
+            "h" | "help" => Command::Help,
+            _ => yeet!(anyhow!("Unknown command: {name}")),
+        };
+        Ok(command)
+    }
+}
+
+/// Why the execution stopped.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum Stop {
+    /// The requested number of instructions has been executed.
+    Stepped,
+    /// Stopped before executing the instruction at this address.
+    Breakpoint(u16),
+    Halted,
+}
+
+pub struct Debugger {
+    pub emulator: Emulator,
+    debug_info: DebugInfo,
+    breakpoints: BTreeSet<u16>,
+}
+
+impl Debugger {
+    /// `debug_info` can be empty if the program is loaded from a binary
+    /// without its sidecar file; breakpoints then can only be set by addresses.
+    pub fn new(emulator: Emulator, debug_info: DebugInfo) -> Self {
+        Self {
+            emulator,
+            debug_info,
+            breakpoints: BTreeSet::new(),
+        }
+    }
+
+    /// Resolves a location, either a label name or an address literal.
+    pub fn resolve(&self, location: &str) -> anyhow::Result<u16> {
+        if let Some(&addr) = self.debug_info.labels.get(location) {
+            return Ok(addr);
+        }
+        parse_u16_literal(location).ok_or(anyhow!("Unknown label or address: {location}"))
+    }
+
+    pub fn add_breakpoint(&mut self, location: &str) -> anyhow::Result<u16> {
+        let addr = self.resolve(location)?;
+        self.breakpoints.insert(addr);
+        Ok(addr)
+    }
+
+    pub fn remove_breakpoint(&mut self, location: &str) -> anyhow::Result<u16> {
+        let addr = self.resolve(location)?;
+        if !self.breakpoints.remove(&addr) {
+            yeet!(anyhow!("No breakpoint at {}", self.describe_address(addr)));
+        }
+        Ok(addr)
+    }
+
+    /// Executes at most `count` instructions.
+    pub fn step(&mut self, count: u64) -> anyhow::Result<Stop> {
+        for _ in 0..count {
+            if self.tick()? {
+                return Ok(Stop::Halted);
+            }
+        }
+        Ok(Stop::Stepped)
+    }
+
+    /// Runs until a breakpoint is hit or the CPU halts.
+    ///
+    /// The instruction at the current PC is always executed, so continuing
+    /// from a breakpoint does not stop at the same place again.
+    pub fn continue_execution(&mut self) -> anyhow::Result<Stop> {
+        loop {
+            if self.tick()? {
+                return Ok(Stop::Halted);
+            }
+            let pc = *self.emulator.pc;
+            if self.breakpoints.contains(&pc) {
+                return Ok(Stop::Breakpoint(pc));
+            }
+        }
+    }
+
+    /// Returns `true` if the CPU halts.
+    fn tick(&mut self) -> anyhow::Result<bool> {
+        if self.emulator.halted {
+            return Ok(true);
+        }
+        self.emulator.tick()?;
+        Ok(self.emulator.halted)
+    }
+
+    /// Takes the program output produced so far.
+    pub fn take_output(&mut self) -> Vec<u8> {
+        self.emulator.take_output()
+    }
+
+    /// Executes a command and returns the text to be printed.
+    pub fn execute(&mut self, command: &Command) -> anyhow::Result<String> {
+        let mut out = String::new();
+        match command {
+            Command::Step(n) => {
+                let stop = self.step(*n)?;
+                self.write_stop(&mut out, stop)?;
+            }
+            Command::Continue => {
+                let stop = self.continue_execution()?;
+                self.write_stop(&mut out, stop)?;
+            }
+            Command::Break(x) => {
+                let addr = self.add_breakpoint(x)?;
+                writeln!(out, "Breakpoint set at {}", self.describe_address(addr))?;
+            }
+            Command::Delete(x) => {
+                let addr = self.remove_breakpoint(x)?;
+                writeln!(out, "Breakpoint deleted at {}", self.describe_address(addr))?;
+            }
+            Command::Breakpoints => {
+                if self.breakpoints.is_empty() {
+                    writeln!(out, "No breakpoints")?;
+                }
+                for &x in &self.breakpoints {
+                    writeln!(out, "{}", self.describe_address(x))?;
+                }
+            }
+            Command::Info => self.write_state(&mut out)?,
+            Command::Labels => {
+                let mut labels = self.debug_info.labels.iter().collect::<Vec<_>>();
+                labels.sort_by_key(|x| (x.1, x.0));
+                for (name, addr) in labels {
+                    writeln!(out, "0x{addr:04x} {name}")?;
+                }
+            }
+            Command::Help => writeln!(out, "{HELP}")?,
+            Command::Quit => {}
+        }
+        Ok(out)
+    }
+
+    fn write_stop(&self, out: &mut String, stop: Stop) -> fmt::Result {
+        match stop {
+            Stop::Stepped => {}
+            Stop::Breakpoint(x) => writeln!(out, "Breakpoint hit at {}", self.describe_address(x))?,
+            Stop::Halted => writeln!(out, "CPU halted")?,
+        }
+        self.write_state(out)
+    }
+
+    /// Formats an address as `0x0010 <label+4>`, using the nearest label
+    /// at or before it.
+    pub fn describe_address(&self, addr: u16) -> String {
+        let nearest = self
+            .debug_info
+            .labels
+            .iter()
+            .filter(|x| *x.1 <= addr)
+            .max_by_key(|x| (*x.1, std::cmp::Reverse(x.0)));
+        match nearest {
+            Some((name, &x)) if x == addr => format!("0x{addr:04x} <{name}>"),
+            Some((name, &x)) => format!("0x{addr:04x} <{name}+{}>", addr - x),
+            None => format!("0x{addr:04x}"),
+        }
+    }
+
+    /// Prints registers, carry, jump address, stacks and RAM.
+    pub fn write_state(&self, out: &mut String) -> fmt::Result {
+        let emulator = &self.emulator;
+        let registers = &emulator.registers;
+        writeln!(out, "pc: {}", self.describe_address(*emulator.pc))?;
+        if let Some(x) = self.debug_info.find(*emulator.pc) {
+            writeln!(out, "  --> {}:{}: {}", x.file, x.line, x.statement)?;
+        }
+        writeln!(
+            out,
+            "carry: {}  jump_address: {}",
+            u8::from(registers.carry()),
+            self.describe_address(registers.jump_address())
+        )?;
+
+        for (i, &value) in registers.tier1().iter().enumerate() {
+            // `io`, `aor` and `azr` hold no state
+            if (12..=14).contains(&i) {
+                continue;
+            }
+            write!(out, "{:>3}: 0x{value:02x}", REGISTER_NAMES[i])?;
+            out.push(if i % 4 == 3 || i == 15 { '\n' } else { ' ' });
+        }
+
+        write_stack_top(out, "stack", &emulator.stack, |x| format!("0x{x:02x}"))?;
+        write_stack_top(out, "f_call_stack", &emulator.f_call_stack, |x| {
+            format!("0x{x:04x}")
+        })?;
+        write_stack_top(out, "f_args_stack", &emulator.f_args_stack, |x| {
+            format!("0x{x:02x}")
+        })?;
+
+        writeln!(out, "ram:")?;
+        for (i, row) in emulator.ram.chunks(16).enumerate() {
+            write!(out, "  {:02x}:", i * 16)?;
+            for x in row {
+                write!(out, " {x:02x}")?;
+            }
+            out.push('\n');
+        }
+        Ok(())
+    }
+}
+
+fn write_stack_top<T: Copy + Default>(
+    out: &mut String,
+    name: &str,
+    stack: &BoundedStack<T>,
+    format: impl Fn(&T) -> String,
+) -> fmt::Result {
+    let top = stack
+        .iter()
+        .rev()
+        .take(STACK_DUMP_DEPTH)
+        .map(format)
+        .collect::<Vec<_>>();
+    writeln!(
+        out,
+        "{name} (sp: {}, top first): {}",
+        stack.pointer(),
+        top.join(" ")
+    )
+}
@@ -0,0 +1,354 @@
+//! Memory-mapped devices.
+//!
+//! A device attached to the [`Emulator`](crate::emulator::Emulator) takes
+//! [`Device::size`] bytes of the RAM address space from its start address.
+//! `ld` and `st` on those addresses go to the device instead of the RAM.
+//!
+//! The devices here, with their registers by offset:
+//!
+//! | Device      | Size | Registers                                                         |
+//! | ----------- | ---- | ----------------------------------------------------------------- |
+//! | [`Console`] | 2    | 0: read the next input byte (0 if none), write an output byte; 1: number of pending input bytes (at most 255) |
+//! | [`Timer`]   | 2    | 0, 1: cycles since the last reset, little-endian; write to reset  |
+//! | [`Random`]  | 1    | 0: read a pseudo-random byte; write to reseed                     |
+//! | [`Keypad`]  | 2    | 0: read the next key press (0 if none); 1: number of pending presses |
+//! | [`Display`] | 4    | 0: x; 1: y; 2: write to set the pixel at (x, y) to a [`PALETTE`] color, read to get it; 3: write 0 to clear, 1 to present the frame |
+
+use std::any::Any;
+use std::collections::VecDeque;
+use std::fmt::{self, Debug, Write};
+
+pub trait Device: Any + Debug {
+    /// Number of addresses it takes.
+    fn size(&self) -> usize;
+
+    /// Reads the register at `offset`, which is below [`Device::size`].
+    fn read(&mut self, offset: u8) -> u8;
+
+    fn write(&mut self, offset: u8, value: u8);
+
+    /// Called after each executed cycle.
+    fn tick(&mut self) {}
+}
+
+/// A byte stream, like the `in` and `out` registers.
+#[derive(Debug, Default)]
+pub struct Console {
+    pub input: VecDeque<u8>,
+    pub output: Vec<u8>,
+}
+
+impl Device for Console {
+    fn size(&self) -> usize {
+        2
+    }
+
+    fn read(&mut self, offset: u8) -> u8 {
+        match offset {
+            0 => self.input.pop_front().unwrap_or(0),
+            _ => self.input.len().min(u8::MAX as usize) as u8,
+        }
+    }
+
+    fn write(&mut self, offset: u8, value: u8) {
+        if offset == 0 {
+            self.output.push(value);
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct Timer {
+    pub cycles: u16,
+}
+
+impl Device for Timer {
+    fn size(&self) -> usize {
+        2
+    }
+
+    fn read(&mut self, offset: u8) -> u8 {
+        self.cycles.to_le_bytes()[offset as usize]
+    }
+
+    fn write(&mut self, _offset: u8, _value: u8) {
+        self.cycles = 0;
+    }
+
+    fn tick(&mut self) {
+        self.cycles = self.cycles.wrapping_add(1);
+    }
+}
+
+/// An xorshift generator, so runs are reproducible.
+#[derive(Debug)]
+pub struct Random {
+    state: u32,
+}
+
+impl Random {
+    pub fn new(seed: u32) -> Self {
+        // xorshift gets stuck at 0
+        Self { state: seed.max(1) }
+    }
+}
+
+impl Default for Random {
+    fn default() -> Self {
+        Self::new(0x2545_f491)
+    }
+}
+
+impl Device for Random {
+    fn size(&self) -> usize {
+        1
+    }
+
+    fn read(&mut self, _offset: u8) -> u8 {
+        let mut x = self.state;
+        x ^= x << 13;
+        x ^= x >> 17;
+        x ^= x << 5;
+        self.state = x;
+        (x >> 24) as u8
+    }
+
+    fn write(&mut self, _offset: u8, value: u8) {
+        *self = Self::new(value.into());
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct Keypad {
+    /// Key codes pressed and not read yet.
+    pub pressed: VecDeque<u8>,
+}
+
+impl Keypad {
+    pub fn press(&mut self, key: u8) {
+        self.pressed.push_back(key);
+    }
+}
+
+impl Device for Keypad {
+    fn size(&self) -> usize {
+        2
+    }
+
+    fn read(&mut self, offset: u8) -> u8 {
+        match offset {
+            0 => self.pressed.pop_front().unwrap_or(0),
+            _ => self.pressed.len().min(u8::MAX as usize) as u8,
+        }
+    }
+
+    fn write(&mut self, _offset: u8, _value: u8) {}
+}
+
+/// Largest size of a pixel in the [`Frame`] images. Scales are clamped to
+/// `1..=MAX_SCALE`, so a 255×255 frame stays under 50 MB of RGB.
+pub const MAX_SCALE: u32 = 16;
+
+/// The 16 colors of the [`Display`], in RGB.
+pub const PALETTE: [[u8; 3]; 16] = [
+    [0x00, 0x00, 0x00],
+    [0x00, 0x00, 0xaa],
+    [0x00, 0xaa, 0x00],
+    [0x00, 0xaa, 0xaa],
+    [0xaa, 0x00, 0x00],
+    [0xaa, 0x00, 0xaa],
+    [0xaa, 0x55, 0x00],
+    [0xaa, 0xaa, 0xaa],
+    [0x55, 0x55, 0x55],
+    [0x55, 0x55, 0xff],
+    [0x55, 0xff, 0x55],
+    [0x55, 0xff, 0xff],
+    [0xff, 0x55, 0x55],
+    [0xff, 0x55, 0xff],
+    [0xff, 0xff, 0x55],
+    [0xff, 0xff, 0xff],
+];
+
+/// A pixel framebuffer. A monochrome program uses the colors 0 and 15.
+pub struct Display {
+    frame: Frame,
+    x: u8,
+    y: u8,
+    /// Number of presented frames.
+    pub presented: u32,
+    /// If set, every `n`th presented frame is kept in `snapshots`, or passed
+    /// to `on_snapshot`.
+    pub snapshot_every: Option<u32>,
+    pub snapshots: Vec<Frame>,
+    /// Takes the snapshots as they're taken, instead of `snapshots`, so a long
+    /// run doesn't pile them up.
+    pub on_snapshot: Option<SnapshotCallback>,
+}
+
+pub type SnapshotCallback = Box<dyn FnMut(&Frame)>;
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Frame {
+    pub width: u8,
+    pub height: u8,
+    /// Palette indices, row by row.
+    pub pixels: Vec<u8>,
+    /// 1-based number of the frame. The one being drawn has the number it'll
+    /// be presented as.
+    pub number: u32,
+}
+
+impl Display {
+    pub fn new(width: u8, height: u8) -> Self {
+        assert!(width > 0 && height > 0, "Display size must not be zero");
+        Self {
+            frame: Frame {
+                width,
+                height,
+                pixels: vec![0; width as usize * height as usize],
+                number: 1,
+            },
+            x: 0,
+            y: 0,
+            presented: 0,
+            snapshot_every: None,
+            snapshots: Vec::new(),
+            on_snapshot: None,
+        }
+    }
+
+    /// The frame being drawn.
+    pub fn frame(&self) -> &Frame {
+        &self.frame
+    }
+
+    fn pixel_index(&self) -> Option<usize> {
+        let (width, height) = (self.frame.width, self.frame.height);
+        (self.x < width && self.y < height)
+            .then(|| self.y as usize * width as usize + self.x as usize)
+    }
+}
+
+impl Default for Display {
+    fn default() -> Self {
+        Self::new(32, 32)
+    }
+}
+
+impl Debug for Display {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Display")
+            .field("frame", &self.frame)
+            .field("x", &self.x)
+            .field("y", &self.y)
+            .field("presented", &self.presented)
+            .field("snapshot_every", &self.snapshot_every)
+            .field("snapshots", &self.snapshots)
+            .field("on_snapshot", &self.on_snapshot.is_some())
+            .finish()
+    }
+}
+
+impl Device for Display {
+    fn size(&self) -> usize {
+        4
+    }
+
+    fn read(&mut self, offset: u8) -> u8 {
+        match offset {
+            0 => self.x,
+            1 => self.y,
+            2 => self.pixel_index().map_or(0, |i| self.frame.pixels[i]),
+            _ => 0,
+        }
+    }
+
+    fn write(&mut self, offset: u8, value: u8) {
+        match offset {
+            0 => self.x = value,
+            1 => self.y = value,
+            2 => {
+                if let Some(i) = self.pixel_index() {
+                    self.frame.pixels[i] = value % PALETTE.len() as u8;
+                }
+            }
+            _ => match value {
+                0 => self.frame.pixels.fill(0),
+                1 => {
+                    self.presented += 1;
+                    if self
+                        .snapshot_every
+                        .is_some_and(|n| self.presented.is_multiple_of(n.max(1)))
+                    {
+                        match &mut self.on_snapshot {
+                            Some(f) => f(&self.frame),
+                            None => self.snapshots.push(self.frame.clone()),
+                        }
+                    }
+                    self.frame.number = self.presented + 1;
+                }
+                _ => {}
+            },
+        }
+    }
+}
+
+impl Frame {
+    /// One character per pixel: `.` for color 0, and the hex digit of the
+    /// others.
+    pub fn to_ascii(&self) -> String {
+        let mut ascii = String::new();
+        for row in self.pixels.chunks(self.width as usize) {
+            for &x in row {
+                match x {
+                    0 => ascii.push('.'),
+                    _ => write!(ascii, "{x:x}").unwrap(),
+                }
+            }
+            ascii.push('\n');
+        }
+        ascii
+    }
+
+    /// RGB bytes of the frame, each pixel scaled to `scale`×`scale`.
+    fn rgb(&self, scale: u32) -> Vec<u8> {
+        let scale = scale.clamp(1, MAX_SCALE) as usize;
+        let mut rgb = Vec::new();
+        for row in self.pixels.chunks(self.width as usize) {
+            let line = row
+                .iter()
+                .flat_map(|&x| PALETTE[x as usize % PALETTE.len()].repeat(scale))
+                .collect::<Vec<_>>();
+            for _ in 0..scale {
+                rgb.extend(&line);
+            }
+        }
+        rgb
+    }
+
+    /// A binary PPM (P6) image.
+    pub fn to_ppm(&self, scale: u32) -> Vec<u8> {
+        let (width, height) = self.scaled_size(scale);
+        let mut ppm = format!("P6\n{width} {height}\n255\n").into_bytes();
+        ppm.extend(self.rgb(scale));
+        ppm
+    }
+
+    pub fn to_png(&self, scale: u32) -> Vec<u8> {
+        let (width, height) = self.scaled_size(scale);
+        let mut png = Vec::new();
+        let mut encoder = png::Encoder::new(&mut png, width, height);
+        encoder.set_color(png::ColorType::Rgb);
+        encoder.set_depth(png::BitDepth::Eight);
+        // writing to a `Vec` doesn't fail
+        let mut writer = encoder.write_header().unwrap();
+        writer.write_image_data(&self.rgb(scale)).unwrap();
+        writer.finish().unwrap();
+        png
+    }
+
+    fn scaled_size(&self, scale: u32) -> (u32, u32) {
+        let scale = scale.clamp(1, MAX_SCALE);
+        (self.width as u32 * scale, self.height as u32 * scale)
+    }
+}
@@ -0,0 +1,167 @@
+//! Turns a program binary back into assembly.
+
+use crate::assembler::INST_LENGTH;
+use crate::header::{Header, HeaderVersion};
+use crate::instruction::{Opcode, Operand};
+use anyhow::anyhow;
+use std::collections::BTreeSet;
+use std::fmt::Write;
+use yeet_ops::yeet;
+
+/// Column where the address comments start.
+const COMMENT_COLUMN: usize = 28;
+
+#[derive(Debug, Clone)]
+pub struct Disassembly {
+    pub code: String,
+    /// Where `code` doesn't reproduce the binary: 0 for a version 2 header the
+    /// assembler wouldn't write, and the end of the code for dropped trailing
+    /// bytes. Both are noted in the comments.
+    ///
+    /// If this is empty, `code` assembles to the exact input binary.
+    pub inexact: Vec<u16>,
+}
+
+/// Name of the synthesized label at `address`.
+pub fn label_name(address: u16) -> String {
+    format!("l_{address:04x}")
+}
+
+/// Fails on an instruction that has no assembly form, like an unknown opcode or
+/// garbage in the unused operand bytes.
+pub fn disassemble(binary: &[u8]) -> anyhow::Result<Disassembly> {
+    let header = Header::parse(binary)?;
+    let entrypoint = header.entrypoint;
+    let code_start = header.size();
+    let code = &binary[code_start..];
+    let instructions = code.chunks_exact(INST_LENGTH as usize);
+    let trailing = instructions.remainder();
+    // labels may also be put after the last instruction
+    let code_end = (code_start + code.len() - trailing.len()) as u16;
+    let is_boundary = |address: u16| {
+        (code_start as u16..=code_end).contains(&address)
+            && (address as usize - code_start).is_multiple_of(INST_LENGTH as usize)
+    };
+
+    if !is_boundary(entrypoint) {
+        yeet!(anyhow!(
+            "Entrypoint 0x{entrypoint:02x} is not at an instruction"
+        ));
+    }
+    let mut labels = BTreeSet::from([entrypoint]);
+
+    let mut lines = Vec::new();
+    let mut inexact = Vec::new();
+    // the assembler only uses version 2 when needed
+    let v1_size = Header::size_of(HeaderVersion::V1, &header.segments);
+    let v1_entrypoint = entrypoint - (code_start - v1_size) as u16;
+    let header_reproduced =
+        header.version == Header::required_version(v1_entrypoint, &header.segments);
+    if !header_reproduced {
+        inexact.push(0);
+    }
+    for (i, inst) in instructions.enumerate() {
+        let address = (code_start + i * INST_LENGTH as usize) as u16;
+        let inst: [u8; 4] = inst.try_into().unwrap();
+        let comment = format!("0x{address:04x}: {}", hex::encode(inst));
+        match decode(&inst) {
+            Some(Statement::Plain(x)) => lines.push((address, x, comment)),
+            Some(Statement::Target(opcode, target)) if is_boundary(target) => {
+                labels.insert(target);
+                lines.push((address, format!("{opcode} {}", label_name(target)), comment));
+            }
+            // not at an instruction, so there's no label to give
+            Some(Statement::Target(opcode, target)) => {
+                lines.push((address, format!("{opcode} 0x{target:04x}"), comment));
+            }
+            None => yeet!(anyhow!(
+                "Instruction at 0x{address:04x} has no assembly form: {}",
+                hex::encode(inst)
+            )),
+        }
+    }
+
+    let mut out = String::new();
+    let multiple = header.segments.len() > 1;
+    for (i, segment) in header.segments.iter().enumerate() {
+        let (mem_start, data) = (segment.mem_start, &segment.data);
+        if multiple {
+            writeln!(out, ".data 0x{mem_start:02x} segment{i}")?;
+        } else if !data.is_empty() || mem_start != 0 {
+            writeln!(out, ".data 0x{mem_start:02x}")?;
+        } else {
+            continue;
+        }
+        if !data.is_empty() {
+            let items = data
+                .iter()
+                .map(|x| format!("0x{x:02x}"))
+                .collect::<Vec<_>>();
+            let name = if multiple {
+                format!("data{i}")
+            } else {
+                "data".into()
+            };
+            writeln!(out, "{name} [{}] _", items.join(", "))?;
+        }
+        writeln!(out)?;
+    }
+    writeln!(out, ".entry {}", label_name(entrypoint))?;
+    if !header_reproduced {
+        writeln!(out, "; the version 2 header is not reproduced")?;
+    }
+    writeln!(out)?;
+    writeln!(out, ".code")?;
+    for (address, statement, comment) in lines {
+        if labels.contains(&address) {
+            writeln!(out, "{}:", label_name(address))?;
+        }
+        let statement = format!("    {statement}");
+        writeln!(out, "{statement:<COMMENT_COLUMN$} ; {comment}")?;
+    }
+    if labels.contains(&code_end) {
+        writeln!(out, "{}:", label_name(code_end))?;
+    }
+    if !trailing.is_empty() {
+        inexact.push(code_end);
+        writeln!(out, "; trailing bytes dropped: {}", hex::encode(trailing))?;
+    }
+
+    Ok(Disassembly { code: out, inexact })
+}
+
+enum Statement {
+    Plain(String),
+    /// `jamv` or `call`, with the target address.
+    Target(Opcode, u16),
+}
+
+/// Returns `None` if `inst` is not what the assembler produces.
+fn decode(inst: &[u8; 4]) -> Option<Statement> {
+    let opcode = Opcode::try_from(inst[0] & 0b00111111).ok()?;
+    let operands = opcode.decode_operands(inst);
+    if opcode.binary(&operands).ok()? != *inst {
+        return None;
+    }
+
+    if opcode == Opcode::JumpAddrMove || opcode == Opcode::Call {
+        return Some(Statement::Target(
+            opcode,
+            u16::from_le_bytes([inst[2], inst[3]]),
+        ));
+    }
+    let mut statement = opcode.to_string();
+    for (i, x) in operands.iter().enumerate() {
+        statement.push(' ');
+        match x {
+            Operand::Immediate(x) => write!(statement, "{x}").unwrap(),
+            Operand::Symbol(x) => {
+                // the destination is always the last operand
+                let is_destination =
+                    opcode.destination_index().is_some() && i == operands.len() - 1;
+                statement.push_str(x.asm_name(is_destination));
+            }
+        }
+    }
+    Some(Statement::Plain(statement))
+}
@@ -1,31 +1,214 @@
-use std::cell::RefCell;
-use std::io::{stdout, Write};
 use crate::assembler::INST_LENGTH;
 use crate::components;
 use crate::components::jump_condition;
+use crate::device::Device;
+use crate::header::{Header, HeaderError};
 use crate::instruction::{
-    Opcode, OpcodeType, COPY_STATIC_HEADER, OPCODE_SUBTYPE_MASK, OPCODE_TYPE_MASK,
+    Opcode, OpcodeType, OperandSymbol, OPCODE_SUBTYPE_MASK, OPCODE_TYPE_MASK,
 };
+use crate::io::{InputSource, OutputSink, Reader};
+use crate::stack::{BoundedStack, OverflowBehavior};
+use crate::trace::{Trace, TraceEntry, TraceWrite};
 use anyhow::anyhow;
+use log::debug;
 use num_traits::{AsPrimitive, WrappingAdd};
+use std::any::Any;
+use std::collections::VecDeque;
+use std::error::Error;
+use std::fmt::{self, Debug, Display, Formatter};
+use std::io::{self, Read};
 use std::ops::{AddAssign, Deref};
+use strum_macros::{Display as StrumDisplay, EnumString};
 use yeet_ops::yeet;
-use log::debug;
 
 #[derive(Default, Debug)]
 pub struct Emulator {
     pub program: Vec<u8>,
     pub pc: WrappingNum<u16>,
     pub ram: Vec<u8>,
-    pub stack: Vec<u8>,
-    pub f_call_stack: Vec<u16>,
-    pub f_args_stack: Vec<u8>,
+    pub stack: BoundedStack<u8>,
+    pub f_call_stack: BoundedStack<u16>,
+    pub f_args_stack: BoundedStack<u8>,
     pub registers: Registers,
     pub halted: bool,
-    pub output: Option<Output>,
-    pub input: RefCell<Vec<u8>>,
+    /// Strict mode. Illegal instructions and undefined behaviors raise a [`Fault`]
+    /// instead of being tolerated like the game hardware does.
+    pub strict: bool,
+    /// The byte written to `out` in the last tick.
+    pub last_output: Option<u8>,
+    /// Where the output goes, in addition to `last_output`.
+    output: Box<dyn OutputSink>,
+    pub input: Input,
+    /// What reading `in` past the end of the input does.
+    pub input_policy: InputPolicy,
+    /// Number of executed ticks.
+    pub cycles: u64,
+    /// Set if the last tick stalled on the input end.
+    input_stalled: bool,
+    /// Execution trace. Recording is enabled if this is `Some`.
+    pub trace: Option<Trace>,
+    /// The trace entry of the ongoing tick.
+    trace_entry: Option<TraceEntry>,
+    /// Memory-mapped devices, which `ld` and `st` reach instead of the RAM.
+    devices: Vec<MappedDevice>,
 }
 
+#[derive(Debug)]
+struct MappedDevice {
+    start: u8,
+    device: Box<dyn Device>,
+}
+
+impl MappedDevice {
+    fn offset_of(&self, address: u8) -> Option<u8> {
+        let offset = address.checked_sub(self.start)?;
+        ((offset as usize) < self.device.size()).then_some(offset)
+    }
+}
+
+/// Why [`Emulator::run`] returned.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum StopReason {
+    Halted,
+    /// The cycle budget ran out before `halt`.
+    BudgetExhausted,
+    /// The program wants more input. See [`InputPolicy::Block`].
+    InputExhausted,
+    Fault(Fault),
+}
+
+/// What an instruction reading `in` past the end of the input does.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Default, EnumString, StrumDisplay)]
+#[strum(ascii_case_insensitive, serialize_all = "lowercase")]
+pub enum InputPolicy {
+    /// The read gives 0.
+    #[default]
+    Zero,
+    /// The instruction is not executed, and [`Emulator::run`] stops with
+    /// [`StopReason::InputExhausted`]. It runs again when resumed, so more
+    /// input can be given in between.
+    Block,
+    /// A [`Fault`] is raised.
+    Fault,
+}
+
+/// Program input: the queued bytes, followed by the ones of a source, which
+/// are pulled only as the program reads them.
+#[derive(Default)]
+pub struct Input {
+    queued: VecDeque<u8>,
+    source: Option<Box<dyn InputSource>>,
+}
+
+impl Input {
+    pub fn from_source(source: impl InputSource + 'static) -> Self {
+        Self {
+            queued: VecDeque::new(),
+            source: Some(Box::new(source)),
+        }
+    }
+
+    pub fn from_reader(reader: impl Read + 'static) -> Self {
+        Self::from_source(Reader(reader))
+    }
+
+    /// Queues `bytes` before the ones still in the source.
+    pub fn extend(&mut self, bytes: impl AsRef<[u8]>) {
+        self.queued.extend(bytes.as_ref());
+    }
+
+    /// Bytes pulled from the source or queued, and not read yet.
+    pub fn queued(&self) -> &VecDeque<u8> {
+        &self.queued
+    }
+
+    /// Whether `n` bytes can be read. Pulls from the source as needed, which
+    /// may block.
+    fn fill(&mut self, n: usize) -> io::Result<bool> {
+        while self.queued.len() < n {
+            let Some(source) = &mut self.source else {
+                return Ok(false);
+            };
+            match source.read_byte()? {
+                Some(x) => self.queued.push_back(x),
+                None => self.source = None,
+            }
+        }
+        Ok(true)
+    }
+
+    fn pop(&mut self) -> Option<u8> {
+        self.queued.pop_front()
+    }
+}
+
+impl From<Vec<u8>> for Input {
+    fn from(value: Vec<u8>) -> Self {
+        Self {
+            queued: value.into(),
+            source: None,
+        }
+    }
+}
+
+impl Debug for Input {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Input")
+            .field("queued", &self.queued)
+            .field("source", &self.source.is_some())
+            .finish()
+    }
+}
+
+/// An error raised in strict mode, or by stacks with [`OverflowBehavior::Fault`].
+/// PC stays at the faulting instruction.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct Fault {
+    pub pc: u16,
+    pub inst: [u8; 4],
+    pub kind: FaultKind,
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum FaultKind {
+    IllegalOpcode,
+    /// Operand byte not being a register code.
+    InvalidRegister(u8),
+    PcOutOfBounds,
+    StackUnderflow(StackKind),
+    StackOverflow(StackKind),
+    DivisionByZero,
+    /// See [`InputPolicy::Fault`].
+    InputExhausted,
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum StackKind {
+    /// The one used by `push` and `pop`.
+    Data,
+    /// Return addresses.
+    Call,
+    /// The one used by `fpush` and `fpop`.
+    Args,
+}
+
+impl Display for Fault {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self.kind {
+            FaultKind::IllegalOpcode => write!(f, "Illegal opcode")?,
+            FaultKind::InvalidRegister(x) => write!(f, "Invalid register operand: 0x{x:02x}")?,
+            FaultKind::PcOutOfBounds => write!(f, "PC out of the program")?,
+            FaultKind::StackUnderflow(x) => write!(f, "{x:?} stack underflow")?,
+            FaultKind::StackOverflow(x) => write!(f, "{x:?} stack overflow")?,
+            FaultKind::DivisionByZero => write!(f, "Division by zero")?,
+            FaultKind::InputExhausted => write!(f, "Reading past the input end")?,
+        }
+        write!(f, " at 0x{:04x} [{}]", self.pc, hex::encode(self.inst))
+    }
+}
+
+impl Error for Fault {}
+
 #[derive(Debug)]
 pub struct Registers {
     /// The 16 registers, represented as a 4-bit number in the operand byte.
@@ -59,56 +242,285 @@ impl Registers {
     fn carry_u8(&self) -> u8 {
         self.carry.into()
     }
+
+    /// All the 16 tier1 register slots, indexed by the register code.
+    pub fn tier1(&self) -> &[u8] {
+        &self.tier1
+    }
+
+    pub fn tier1_mut(&mut self) -> &mut [u8] {
+        &mut self.tier1
+    }
+
+    pub fn carry(&self) -> bool {
+        self.carry
+    }
+
+    pub fn set_carry(&mut self, carry: bool) {
+        self.carry = carry;
+    }
+
+    pub fn jump_address(&self) -> u16 {
+        self.jump_address
+    }
+
+    pub fn set_jump_address(&mut self, address: u16) {
+        self.jump_address = address;
+    }
 }
 
 impl Emulator {
+    /// The error may be a [`HeaderError`].
     pub fn new(binary: impl Into<Vec<u8>>) -> anyhow::Result<Self> {
         let mut emulator = Self {
             program: binary.into(),
             pc: 0.into(),
             ram: vec![0; u8::MAX as usize + 1],
-            stack: vec![0; u8::MAX as usize + 1],
-            f_call_stack: vec![0; u8::MAX as usize + 1],
-            f_args_stack: vec![0; u8::MAX as usize + 1],
+            stack: BoundedStack::default(),
+            f_call_stack: BoundedStack::default(),
+            f_args_stack: BoundedStack::default(),
             registers: Registers::default(),
             halted: false,
-            output: None,
-            input: vec![].into(),
+            strict: false,
+            last_output: None,
+            output: Box::default(),
+            input: Input::default(),
+            input_policy: InputPolicy::Zero,
+            cycles: 0,
+            input_stalled: false,
+            trace: None,
+            trace_entry: None,
+            devices: Vec::new(),
         };
         emulator.parse_header()?;
         Ok(emulator)
     }
 
+    /// Replaces the input with `input`.
     pub fn set_input(&mut self, input: impl Into<Vec<u8>>) -> &mut Self {
-        // because of taking input uses `pop`, the order is reversed
-        let mut vec = input.into();
-        vec.reverse();
-        self.input = vec.into();
+        self.input = input.into().into();
+        self
+    }
+
+    /// Replaces the input with the bytes of `reader`, read as the program asks
+    /// for them.
+    pub fn set_input_reader(&mut self, reader: impl Read + 'static) -> &mut Self {
+        self.input = Input::from_reader(reader);
+        self
+    }
+
+    /// Replaces the input with the bytes of `source`, read as the program asks
+    /// for them.
+    pub fn set_input_source(&mut self, source: impl InputSource + 'static) -> &mut Self {
+        self.input = Input::from_source(source);
+        self
+    }
+
+    /// Sends the output from now on to `sink`, instead of collecting it in a
+    /// `Vec<u8>`.
+    pub fn set_output(&mut self, sink: impl OutputSink) -> &mut Self {
+        self.output = Box::new(sink);
+        self
+    }
+
+    /// The output sink, if it's a `T`.
+    pub fn output<T: OutputSink>(&self) -> Option<&T> {
+        (&*self.output as &dyn Any).downcast_ref()
+    }
+
+    pub fn output_mut<T: OutputSink>(&mut self) -> Option<&mut T> {
+        (&mut *self.output as &mut dyn Any).downcast_mut()
+    }
+
+    /// Takes the output collected so far. Empty if it's sent to another sink
+    /// than the default `Vec<u8>`.
+    pub fn take_output(&mut self) -> Vec<u8> {
+        self.output_mut::<Vec<u8>>()
+            .map(std::mem::take)
+            .unwrap_or_default()
+    }
+
+    pub fn set_input_policy(&mut self, policy: InputPolicy) -> &mut Self {
+        self.input_policy = policy;
+        self
+    }
+
+    pub fn set_strict(&mut self, strict: bool) -> &mut Self {
+        self.strict = strict;
         self
     }
 
-    fn parse_header(&mut self) -> anyhow::Result<()> {
-        let header = &self.program[..4];
-        if header[0] != COPY_STATIC_HEADER {
-            yeet!(anyhow!("Invalid header: {:?}", header));
+    /// Replaces all the three stacks with empty ones of `depth` entries.
+    ///
+    /// In strict mode, overflows and underflows fault regardless of `behavior`.
+    pub fn set_stacks(&mut self, depth: usize, behavior: OverflowBehavior) -> &mut Self {
+        self.stack = BoundedStack::new(depth, behavior);
+        self.f_call_stack = BoundedStack::new(depth, behavior);
+        self.f_args_stack = BoundedStack::new(depth, behavior);
+        self
+    }
+
+    /// Maps `device` to the RAM addresses from `start`. It can't overlap the
+    /// attached ones.
+    pub fn attach(&mut self, start: u8, device: impl Device) -> anyhow::Result<&mut Self> {
+        let end = start as usize + device.size();
+        if end > self.ram.len() {
+            yeet!(anyhow!(
+                "Device at 0x{start:02x} of {} bytes exceeds the RAM address space",
+                device.size()
+            ));
         }
+        if let Some(x) = self.devices.iter().find(|x| {
+            let x_end = x.start as usize + x.device.size();
+            (start as usize) < x_end && (x.start as usize) < end
+        }) {
+            yeet!(anyhow!(
+                "Device at 0x{start:02x}..0x{end:02x} overlaps the one at 0x{:02x}..0x{:02x}",
+                x.start,
+                x.start as usize + x.device.size()
+            ));
+        }
+        self.devices.push(MappedDevice {
+            start,
+            device: Box::new(device),
+        });
+        Ok(self)
+    }
 
-        let data_len = header[1] as usize;
-        let mem_start = header[2] as usize;
-        let entrypoint: u16 = header[3] as u16;
+    /// The first attached device of type `T`.
+    pub fn device<T: Device>(&self) -> Option<&T> {
+        self.devices
+            .iter()
+            .find_map(|x| (&*x.device as &dyn Any).downcast_ref())
+    }
+
+    pub fn device_mut<T: Device>(&mut self) -> Option<&mut T> {
+        self.devices
+            .iter_mut()
+            .find_map(|x| (&mut *x.device as &mut dyn Any).downcast_mut())
+    }
+
+    /// Reads `address` from the device mapped there, or the RAM.
+    fn mem_read(&mut self, address: u8) -> u8 {
+        for x in &mut self.devices {
+            if let Some(offset) = x.offset_of(address) {
+                return x.device.read(offset);
+            }
+        }
+        self.ram[address as usize]
+    }
+
+    fn mem_write(&mut self, address: u8, value: u8) {
+        for x in &mut self.devices {
+            if let Some(offset) = x.offset_of(address) {
+                x.device.write(offset, value);
+                return;
+            }
+        }
+        self.ram[address as usize] = value;
+    }
 
-        let static_data = &self.program[4..(4 + data_len)];
-        self.ram[mem_start..(mem_start + data_len)].copy_from_slice(static_data);
-        self.pc = entrypoint.into();
+    /// Starts recording the execution trace.
+    pub fn enable_trace(&mut self) -> &mut Self {
+        self.trace = Some(Trace::default());
+        self
+    }
+
+    /// Loads the data segments and jumps to the entrypoint. Both header
+    /// versions are accepted.
+    fn parse_header(&mut self) -> Result<(), HeaderError> {
+        let header = Header::parse(&self.program)?;
+        for x in &header.segments {
+            let start = x.mem_start as usize;
+            let Some(ram) = self.ram.get_mut(start..(start + x.data.len())) else {
+                yeet!(HeaderError::SegmentPastRam {
+                    mem_start: x.mem_start,
+                    len: x.data.len(),
+                });
+            };
+            ram.copy_from_slice(&x.data);
+        }
+        self.pc = header.entrypoint.into();
 
         Ok(())
     }
 
+    /// Executes one instruction. The error may be a [`Fault`], or an I/O
+    /// error of the input source or the output sink.
     pub fn tick(&mut self) -> anyhow::Result<()> {
         if self.halted {
             yeet!(anyhow!("CPU is halted"));
         }
+        self.input_stalled = false;
+        let result = match self.trace {
+            None => self.execute(),
+            Some(_) => self.execute_traced(),
+        };
+        if result.is_ok() && !self.input_stalled {
+            self.cycles += 1;
+            for x in &mut self.devices {
+                x.device.tick();
+            }
+            if let Some(x) = self.last_output {
+                self.output.write(x, self.cycles)?;
+            }
+        }
+        result
+    }
+
+    fn execute_traced(&mut self) -> anyhow::Result<()> {
+        self.trace_entry = Some(TraceEntry {
+            pc: *self.pc,
+            inst: self.fetch(),
+            ..Default::default()
+        });
+        let carry = self.registers.carry;
+        let jump_address = self.registers.jump_address;
+
+        let result = self.execute();
+
+        let mut entry = self.trace_entry.take().unwrap();
+        if result.is_err() || self.input_stalled {
+            // nothing was completed
+            return result;
+        }
+        if self.registers.carry != carry {
+            entry.carry = Some(self.registers.carry);
+        }
+        if self.registers.jump_address != jump_address {
+            entry.write = Some(TraceWrite::JumpAddress(self.registers.jump_address));
+        }
+        entry.output = self.last_output;
+        self.trace.as_mut().unwrap().entries.push(entry);
+        result
+    }
+
+    fn pc_in_bounds(&self) -> bool {
+        self.pc.usize() + INST_LENGTH as usize <= self.program.len()
+    }
+
+    /// Fetches the instruction at PC.
+    fn fetch(&self) -> [u8; 4] {
+        if !self.pc_in_bounds() {
+            // PC goes beyond the available program area
+            // this may happen if jumping to an invalid program address,
+            // or program runs without a `halt`.
+            // just issue [0, 0, 0, 0] if this happens.
+            NULL_INSTRUCTION
+        } else {
+            self.program[self.pc.usize()..(self.pc.usize() + INST_LENGTH as usize)]
+                .try_into()
+                .unwrap()
+        }
+    }
+
+    fn record(&mut self, f: impl FnOnce(&mut TraceEntry)) {
+        if let Some(x) = &mut self.trace_entry {
+            f(x);
+        }
+    }
 
+    fn execute(&mut self) -> anyhow::Result<()> {
         macro end_not_add_pc() {{
             return Ok(());
         }}
@@ -120,20 +532,27 @@ impl Emulator {
 
         // every tick, reset the output.
         // output is only valid if enabled in Turing Complete
-        self.output = None;
+        self.last_output = None;
 
-        let inst = if self.pc.usize() + INST_LENGTH as usize - 1 > self.program.len() {
-            // PC goes beyond the available program area
-            // this may happen if jumping to an invalid program address,
-            // or program runs without a `halt`.
-            // just issue [0, 0, 0, 0] if this happens.
-            &NULL_INSTRUCTION
-        } else {
-            &self.program[self.pc.usize()..(self.pc.usize() + INST_LENGTH as usize)]
-        };
+        let inst = self.fetch();
+
+        macro fault($kind:expr) {{
+            yeet!(anyhow::Error::from(Fault {
+                pc: *self.pc,
+                inst,
+                kind: $kind,
+            }))
+        }}
+
+        if self.strict && !self.pc_in_bounds() {
+            fault!(FaultKind::PcOutOfBounds);
+        }
 
         let opcode_u8 = inst[0] & 0b00111111;
         let Ok(opcode) = Opcode::try_from(opcode_u8) else {
+            if self.strict {
+                fault!(FaultKind::IllegalOpcode);
+            }
             // skip unknown opcodes
             end!()
         };
@@ -148,25 +567,75 @@ impl Emulator {
             end!()
         };
 
-        macro get_operand($imm:expr, $inst_index:expr) {
-            if $imm {
+        // only source operands are fetched, so a destination `out` won't consume input
+        let read1 = opcode.reads_operand(1);
+        let read2 = opcode.reads_operand(2);
+
+        let reads = [(read1 && !imm1, inst[1]), (read2 && !imm2, inst[2])]
+            .iter()
+            .filter(|x| x.0 && x.1 == OperandSymbol::InOut as u8)
+            .count();
+        if reads > 0 && !self.input.fill(reads)? {
+            match self.input_policy {
+                InputPolicy::Zero => {}
+                InputPolicy::Block => {
+                    // leave PC here, so the instruction runs again when resumed
+                    self.input_stalled = true;
+                    end_not_add_pc!();
+                }
+                InputPolicy::Fault => fault!(FaultKind::InputExhausted),
+            }
+        }
+
+        macro get_operand($read:expr, $imm:expr, $inst_index:expr) {
+            if !$read {
+                0
+            } else if $imm {
                 inst[$inst_index]
             } else {
-                let Ok(reg) = crate::instruction::OperandSymbol::try_from(inst[$inst_index]) else {
+                let Ok(reg) = OperandSymbol::try_from(inst[$inst_index]) else {
+                    if self.strict {
+                        fault!(FaultKind::InvalidRegister(inst[$inst_index]));
+                    }
                     end!()
                 };
                 self.reg_fetch(reg as u8)
             }
         }
-        let operand1 = get_operand!(imm1, 1);
-        let operand2 = get_operand!(imm2, 2);
+        let operand1 = get_operand!(read1, imm1, 1);
+        let operand2 = get_operand!(read2, imm2, 2);
+        self.record(|x| x.operands = [operand1, operand2]);
 
         let opcode_subtype = opcode_u8 & OPCODE_SUBTYPE_MASK;
 
+        macro write_reg($reg:expr, $value:expr) {{
+            let reg = $reg;
+            if self.strict && OperandSymbol::try_from(reg).is_err() {
+                fault!(FaultKind::InvalidRegister(reg));
+            }
+            self.reg_write(reg, $value);
+        }}
+
+        macro push($stack:expr, $kind:expr, $value:expr) {{
+            if (self.strict && $stack.is_full()) || $stack.push($value).is_err() {
+                fault!(FaultKind::StackOverflow($kind));
+            }
+        }}
+
+        macro pop($stack:expr, $kind:expr) {{
+            if self.strict && $stack.is_empty() {
+                fault!(FaultKind::StackUnderflow($kind));
+            }
+            match $stack.pop() {
+                Ok(x) => x,
+                Err(_) => fault!(FaultKind::StackUnderflow($kind)),
+            }
+        }}
+
         match opcode_type {
             OpcodeType::Compute => {
                 let out = components::alu(opcode_u8, operand1, operand2);
-                self.reg_write(inst[3], out.out);
+                write_reg!(inst[3], out.out);
                 self.registers.carry = out.carry;
             }
             OpcodeType::ConditionalJumping => {
@@ -181,12 +650,13 @@ impl Emulator {
                 match opcode_subtype {
                     0b000 => {
                         // load
-                        let v = self.ram[operand1 as usize];
-                        self.reg_write(inst[2], v);
+                        let v = self.mem_read(operand1);
+                        write_reg!(inst[2], v);
                     }
                     0b001 => {
                         // store
-                        self.ram[operand1 as usize] = operand2;
+                        self.mem_write(operand1, operand2);
+                        self.record(|x| x.write = Some(TraceWrite::Ram(operand1, operand2)));
                     }
                     _ => {}
                 }
@@ -195,12 +665,12 @@ impl Emulator {
                 match opcode_subtype {
                     0b000 => {
                         // push
-                        self.stack.push(operand1);
+                        push!(self.stack, StackKind::Data, operand1);
                     }
                     0b001 => {
                         // pop
-                        let value = self.stack.pop().unwrap_or_default();
-                        self.reg_write(inst[1], value);
+                        let value = pop!(self.stack, StackKind::Data);
+                        write_reg!(inst[1], value);
                     }
                     _ => {}
                 }
@@ -210,7 +680,7 @@ impl Emulator {
                     0b000 => {
                         // call
                         // push the address of the next instruction (known as the return address)
-                        self.f_call_stack.push(*self.pc + 4);
+                        push!(self.f_call_stack, StackKind::Call, *self.pc + 4);
                         let call_addr = u16::from_le_bytes([inst[2], inst[3]]);
                         // jump to function
                         self.pc = call_addr.into();
@@ -219,58 +689,64 @@ impl Emulator {
                     0b001 => {
                         // return
                         // pop the return-address and set the PC
-                        let addr = self.f_call_stack.pop().unwrap_or_default();
+                        let addr = pop!(self.f_call_stack, StackKind::Call);
                         self.pc = addr.into();
                         end_not_add_pc!();
                     }
                     0b010 => {
                         // fpush
-                        self.f_args_stack.push(operand1);
+                        push!(self.f_args_stack, StackKind::Args, operand1);
                     }
                     0b011 => {
                         // fpop
-                        let value = self.f_args_stack.pop().unwrap_or_default();
-                        self.reg_write(inst[1], value);
+                        let value = pop!(self.f_args_stack, StackKind::Args);
+                        write_reg!(inst[1], value);
                     }
                     _ => {}
                 }
             }
             OpcodeType::Shifts => {
                 let out = components::shift(opcode_u8, operand1, operand2);
-                self.reg_write(inst[3], out);
+                write_reg!(inst[3], out);
             }
             OpcodeType::ArithmeticSupplementary => {
                 match opcode_subtype {
                     0b000 => {
                         // div
-                        self.reg_write(inst[3], operand1 / operand2);
+                        if operand2 == 0 && self.strict {
+                            fault!(FaultKind::DivisionByZero);
+                        }
+                        write_reg!(inst[3], operand1.checked_div(operand2).unwrap_or(0));
                     }
                     0b001 => {
                         // mod
-                        self.reg_write(inst[3], operand1 % operand2);
+                        if operand2 == 0 && self.strict {
+                            fault!(FaultKind::DivisionByZero);
+                        }
+                        write_reg!(inst[3], operand1.checked_rem(operand2).unwrap_or(0));
                     }
                     0b010 => {
                         // carry-add
-                        let (r1, c1) = operand1.carrying_add(operand2, false);
-                        let (r2, c2) = r1.carrying_add(self.registers.carry_u8(), false);
-                        self.reg_write(inst[3], r2);
+                        let (r1, c1) = operand1.overflowing_add(operand2);
+                        let (r2, c2) = r1.overflowing_add(self.registers.carry_u8());
+                        write_reg!(inst[3], r2);
                         // also set the carry bit
                         self.registers.carry = c1 || c2;
                     }
                     0b011 => {
                         // add-no-carry
                         let value = operand1.wrapping_add(operand2);
-                        self.reg_write(inst[3], value);
+                        write_reg!(inst[3], value);
                     }
                     0b100 => {
                         // sub-no-carry
                         let value = operand1.wrapping_sub(operand2);
-                        self.reg_write(inst[3], value);
+                        write_reg!(inst[3], value);
                     }
                     0b101 => {
                         // move-carry
                         let value = self.registers.carry_u8();
-                        self.reg_write(inst[3], value);
+                        write_reg!(inst[3], value);
                     }
                     _ => {}
                 }
@@ -283,7 +759,7 @@ impl Emulator {
                     }
                     0b011 => {
                         // copy
-                        self.reg_write(inst[3], operand1);
+                        write_reg!(inst[3], operand1);
                     }
                     0b100 => {
                         // jump-address move
@@ -301,13 +777,15 @@ impl Emulator {
         end!()
     }
 
-    fn reg_fetch(&self, reg: u8) -> u8 {
+    fn reg_fetch(&mut self, reg: u8) -> u8 {
         match reg {
             // r0 to r11
             _ if reg <= 11 => self.registers.tier1[reg as usize],
             12 => {
                 // read input
-                self.input.borrow_mut().pop().unwrap_or(0)
+                let value = self.input.pop().unwrap_or(0);
+                self.record(|x| x.input = Some(value));
+                value
             }
             // always one
             13 => 1,
@@ -324,10 +802,11 @@ impl Emulator {
         match reg {
             _ if reg <= 11 || reg == 15 => {
                 self.registers.tier1[reg as usize] = n;
+                self.record(|x| x.write = Some(TraceWrite::Register(reg, n)));
             }
             12 => {
                 // output
-                self.output = Some(n.into());
+                self.last_output = Some(n);
             }
             // writing to always x registers takes no effect
             13 => {}
@@ -337,51 +816,45 @@ impl Emulator {
         }
     }
 
-    pub fn run_to_halt(&mut self) -> anyhow::Result<Vec<u8>> {
-        let mut output = Vec::new();
+    /// Runs until the CPU halts, or `max_cycles` ticks have been executed by this call.
+    ///
+    /// A [`Fault`] also stops the run, as [`StopReason::Fault`].
+    pub fn run(&mut self, max_cycles: Option<u64>) -> anyhow::Result<StopReason> {
+        let start = self.cycles;
         loop {
-            self.tick()?;
             if self.halted {
-                break;
+                return Ok(StopReason::Halted);
             }
-            if let Some(x) = self.output {
-                output.push(*x);
+            if max_cycles.is_some_and(|x| self.cycles - start >= x) {
+                return Ok(StopReason::BudgetExhausted);
             }
-        }
-        Ok(output)
-    }
-
-    pub fn run_to_halt_with_print(&mut self) -> anyhow::Result<()> {
-        let mut stdout = stdout();
-        loop {
-            self.tick()?;
-            if self.halted {
-                break;
+            if let Err(e) = self.tick() {
+                return match e.downcast::<Fault>() {
+                    Ok(x) => Ok(StopReason::Fault(x)),
+                    Err(e) => Err(e),
+                };
             }
-            if let Some(x) = self.output {
-                stdout.write_all(&[*x])?;
-                stdout.flush()?;
+            if self.input_stalled {
+                return Ok(StopReason::InputExhausted);
             }
         }
-        Ok(())
     }
-}
-
-#[repr(transparent)]
-#[derive(Debug, Copy, Clone)]
-pub struct Output(u8);
-
-impl Deref for Output {
-    type Target = u8;
 
-    fn deref(&self) -> &Self::Target {
-        &self.0
+    /// Returns the output collected so far, as [`Emulator::take_output`].
+    pub fn run_to_halt(&mut self) -> anyhow::Result<Vec<u8>> {
+        if let StopReason::Fault(x) = self.run(None)? {
+            yeet!(anyhow::Error::from(x));
+        }
+        Ok(self.take_output())
     }
-}
 
-impl From<u8> for Output {
-    fn from(value: u8) -> Self {
-        Self(value)
+    /// Prints the output from now on to stdout.
+    pub fn run_to_halt_with_print(&mut self) -> anyhow::Result<()> {
+        self.set_output(crate::io::stdout());
+        if let StopReason::Fault(x) = self.run(None)? {
+            yeet!(anyhow::Error::from(x));
+        }
+        Ok(())
     }
 }
 
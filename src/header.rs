@@ -0,0 +1,188 @@
+//! The program header, which precedes the code.
+//!
+//! Version 1, the `copystatic` header the game circuit loads:
+//!
+//! ```text
+//! [COPY_STATIC_HEADER, data_len, mem_start, entrypoint] data...
+//! ```
+//!
+//! Version 2, for a 16-bit entrypoint and several data segments:
+//!
+//! ```text
+//! [HEADER_V2, entry_lo, entry_hi, segment_count]
+//! [mem_start, len_lo, len_hi] (per segment)
+//! data... (the segments, in order)
+//! ```
+//!
+//! The first byte tells the version. The code follows the header directly, so
+//! the code addresses depend on the header size.
+
+use crate::instruction::COPY_STATIC_HEADER;
+use anyhow::anyhow;
+use std::error::Error;
+use std::fmt::{self, Display, Formatter};
+use yeet_ops::yeet;
+
+/// The first byte of a version 2 header.
+pub const HEADER_V2: u8 = 0x02;
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Default)]
+pub enum HeaderVersion {
+    #[default]
+    V1,
+    V2,
+}
+
+/// Data copied into RAM before running.
+#[derive(Debug, Clone, Eq, PartialEq, Default)]
+pub struct DataSegment {
+    pub mem_start: u8,
+    pub data: Vec<u8>,
+}
+
+/// Why a binary can't be loaded.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum HeaderError {
+    /// Fewer than the 4 bytes every header starts with.
+    TooShort,
+    /// The segment table or the data goes past the end of the binary.
+    Truncated,
+    /// The first byte, which isn't a known version.
+    UnknownVersion(u8),
+    /// A data segment that doesn't fit in the 256-byte RAM.
+    SegmentPastRam { mem_start: u8, len: usize },
+}
+
+impl Display for HeaderError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match *self {
+            HeaderError::TooShort => write!(f, "Binary too short for a header"),
+            HeaderError::Truncated => write!(f, "Header truncated"),
+            HeaderError::UnknownVersion(x) => write!(f, "Unknown header version: 0x{x:02x}"),
+            HeaderError::SegmentPastRam { mem_start, len } => write!(
+                f,
+                "Data segment at 0x{mem_start:02x} of {len} bytes exceeds the RAM"
+            ),
+        }
+    }
+}
+
+impl Error for HeaderError {}
+
+#[derive(Debug, Clone, Eq, PartialEq, Default)]
+pub struct Header {
+    pub version: HeaderVersion,
+    pub entrypoint: u16,
+    pub segments: Vec<DataSegment>,
+}
+
+impl Header {
+    /// The smallest version that can hold the entrypoint and the segments.
+    pub fn required_version(entrypoint: u16, segments: &[DataSegment]) -> HeaderVersion {
+        let fits_v1 = match segments {
+            [] => true,
+            [x] => x.data.len() <= u8::MAX as usize,
+            _ => false,
+        };
+        if fits_v1 && entrypoint <= u8::MAX as u16 {
+            HeaderVersion::V1
+        } else {
+            HeaderVersion::V2
+        }
+    }
+
+    /// Size of a header of `version`, including the data.
+    pub fn size_of(version: HeaderVersion, segments: &[DataSegment]) -> usize {
+        let data_len = segments.iter().map(|x| x.data.len()).sum::<usize>();
+        match version {
+            HeaderVersion::V1 => 4 + data_len,
+            HeaderVersion::V2 => 4 + 3 * segments.len() + data_len,
+        }
+    }
+
+    /// Size of the encoded header, which is where the code starts.
+    pub fn size(&self) -> usize {
+        Self::size_of(self.version, &self.segments)
+    }
+
+    /// Parses the header at the start of `binary`.
+    pub fn parse(binary: &[u8]) -> Result<Self, HeaderError> {
+        let Some(&[kind, b1, b2, b3]) = binary.get(..4) else {
+            yeet!(HeaderError::TooShort);
+        };
+        let truncated = || HeaderError::Truncated;
+        match kind {
+            COPY_STATIC_HEADER => {
+                let (data_len, mem_start) = (b1 as usize, b2);
+                let data = binary.get(4..(4 + data_len)).ok_or_else(truncated)?;
+                Ok(Self {
+                    version: HeaderVersion::V1,
+                    entrypoint: b3 as u16,
+                    segments: vec![DataSegment {
+                        mem_start,
+                        data: data.into(),
+                    }],
+                })
+            }
+            HEADER_V2 => {
+                let entrypoint = u16::from_le_bytes([b1, b2]);
+                let count = b3 as usize;
+                let table = binary.get(4..(4 + 3 * count)).ok_or_else(truncated)?;
+                let mut offset = 4 + table.len();
+                let mut segments = Vec::new();
+                for x in table.chunks_exact(3) {
+                    let len = u16::from_le_bytes([x[1], x[2]]) as usize;
+                    let data = binary.get(offset..(offset + len)).ok_or_else(truncated)?;
+                    offset += len;
+                    segments.push(DataSegment {
+                        mem_start: x[0],
+                        data: data.into(),
+                    });
+                }
+                Ok(Self {
+                    version: HeaderVersion::V2,
+                    entrypoint,
+                    segments,
+                })
+            }
+            _ => yeet!(HeaderError::UnknownVersion(kind)),
+        }
+    }
+
+    /// Encodes the header with its data.
+    pub fn encode(&self) -> anyhow::Result<Vec<u8>> {
+        let mut binary = Vec::with_capacity(self.size());
+        match self.version {
+            HeaderVersion::V1 => {
+                let (data, mem_start) = match &self.segments[..] {
+                    [] => (&[][..], 0),
+                    [x] => (&x.data[..], x.mem_start),
+                    _ => yeet!(anyhow!("Version 1 header supports only one data segment")),
+                };
+                let data_len = u8::try_from(data.len())
+                    .map_err(|_| anyhow!("Data too long for a version 1 header"))?;
+                let entrypoint = u8::try_from(self.entrypoint)
+                    .map_err(|_| anyhow!("Version 1 header supports only 8-bit entrypoints"))?;
+                binary.extend([COPY_STATIC_HEADER, data_len, mem_start, entrypoint]);
+                binary.extend(data);
+            }
+            HeaderVersion::V2 => {
+                let count = u8::try_from(self.segments.len())
+                    .map_err(|_| anyhow!("Too many data segments"))?;
+                binary.push(HEADER_V2);
+                binary.extend(self.entrypoint.to_le_bytes());
+                binary.push(count);
+                for x in &self.segments {
+                    let len = u16::try_from(x.data.len())
+                        .map_err(|_| anyhow!("Data segment too long"))?;
+                    binary.push(x.mem_start);
+                    binary.extend(len.to_le_bytes());
+                }
+                for x in &self.segments {
+                    binary.extend(&x.data);
+                }
+            }
+        }
+        Ok(binary)
+    }
+}
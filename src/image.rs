@@ -0,0 +1,205 @@
+//! Program images in text formats for other tools, and loading them back.
+//!
+//! | Format              | Content                                                    |
+//! | ------------------- | ---------------------------------------------------------- |
+//! | Intel HEX           | 16-byte data records, then the end-of-file record          |
+//! | Logisim-evolution   | `v2.0 raw`, then 16 bytes per line                         |
+//! | Hex lines           | One byte per line                                          |
+//! | Verilog `$readmemh` | `@0000`, then 16 bytes per line                            |
+//! | Turing Complete     | The header on the first line, then one instruction per line, in `0x` literals |
+//!
+//! [`load`] tells the format by the content, so a raw binary and all of the
+//! above, as well as the `commented_binary` output, can be run.
+
+use crate::assembler::{BinaryParts, INST_LENGTH};
+use crate::header::HEADER_V2;
+use crate::instruction::COPY_STATIC_HEADER;
+use anyhow::anyhow;
+use std::fmt::Write;
+use yeet_ops::yeet;
+
+/// Bytes per line in the formats that put several on one.
+const LINE_BYTES: usize = 16;
+/// The 16-bit program address space.
+const MAX_SIZE: usize = 0x10000;
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum ImageFormat {
+    IntelHex,
+    Logisim,
+    HexLines,
+    Verilog,
+    TuringComplete,
+}
+
+impl ImageFormat {
+    /// The file extension used for this format.
+    pub fn extension(self) -> &'static str {
+        match self {
+            ImageFormat::IntelHex => "hex",
+            ImageFormat::Logisim => "img",
+            ImageFormat::HexLines | ImageFormat::TuringComplete => "txt",
+            ImageFormat::Verilog => "mem",
+        }
+    }
+
+    pub fn write(self, binary: &BinaryParts) -> String {
+        let bytes = binary.merge();
+        let mut out = String::new();
+        match self {
+            ImageFormat::IntelHex => {
+                for (i, x) in bytes.chunks(LINE_BYTES).enumerate() {
+                    let address = ((i * LINE_BYTES) as u16).to_be_bytes();
+                    let record = [&[x.len() as u8], &address[..], &[0], x].concat();
+                    let checksum = record
+                        .iter()
+                        .fold(0_u8, |a, &b| a.wrapping_add(b))
+                        .wrapping_neg();
+                    writeln!(out, ":{}{checksum:02X}", hex::encode_upper(record)).unwrap();
+                }
+                writeln!(out, ":00000001FF").unwrap();
+            }
+            ImageFormat::Logisim => {
+                writeln!(out, "v2.0 raw").unwrap();
+                write_lines(&mut out, &bytes, LINE_BYTES, "");
+            }
+            ImageFormat::HexLines => write_lines(&mut out, &bytes, 1, ""),
+            ImageFormat::Verilog => {
+                writeln!(out, "@0000").unwrap();
+                write_lines(&mut out, &bytes, LINE_BYTES, "");
+            }
+            ImageFormat::TuringComplete => {
+                if !binary.header.is_empty() {
+                    write_lines(&mut out, &binary.header, binary.header.len(), "0x");
+                }
+                write_lines(&mut out, &binary.code, INST_LENGTH as usize, "0x");
+            }
+        }
+        out
+    }
+}
+
+fn write_lines(out: &mut String, bytes: &[u8], per_line: usize, prefix: &str) {
+    for line in bytes.chunks(per_line) {
+        let line = line
+            .iter()
+            .map(|x| format!("{prefix}{x:02x}"))
+            .collect::<Vec<_>>();
+        writeln!(out, "{}", line.join(" ")).unwrap();
+    }
+}
+
+/// Reads a program binary, either raw or in one of the [`ImageFormat`]s.
+///
+/// A raw binary starts with a header version, which is not a printable
+/// character.
+pub fn load(data: &[u8]) -> anyhow::Result<Vec<u8>> {
+    if let Some(&x) = data.first()
+        && (x == COPY_STATIC_HEADER || x == HEADER_V2)
+    {
+        return Ok(data.into());
+    }
+    let text = std::str::from_utf8(data).map_err(|_| anyhow!("Unknown program format"))?;
+    let text = text.trim_start();
+    if text.starts_with(':') {
+        parse_intel_hex(text)
+    } else if let Some(x) = text.strip_prefix("v2.0 raw") {
+        parse_logisim(x)
+    } else {
+        parse_hex_text(text)
+    }
+}
+
+fn parse_intel_hex(text: &str) -> anyhow::Result<Vec<u8>> {
+    let mut binary = Vec::new();
+    for (line_no, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        let error = |message: &str| anyhow!("Intel HEX line {}: {message}", line_no + 1);
+        let record = line
+            .strip_prefix(':')
+            .and_then(|x| hex::decode(x).ok())
+            .filter(|x| x.len() >= 5)
+            .ok_or_else(|| error("invalid record"))?;
+        if record.iter().fold(0_u8, |a, &b| a.wrapping_add(b)) != 0 {
+            yeet!(error("checksum mismatch"));
+        }
+        let (len, address, kind) = (
+            record[0] as usize,
+            u16::from_be_bytes([record[1], record[2]]) as usize,
+            record[3],
+        );
+        let Some(data) = record.get(4..(4 + len)).filter(|_| record.len() == 5 + len) else {
+            yeet!(error("wrong record length"));
+        };
+        match kind {
+            0x00 => {
+                if binary.len() < address + len {
+                    binary.resize(address + len, 0);
+                }
+                binary[address..(address + len)].copy_from_slice(data);
+            }
+            0x01 => return Ok(binary),
+            // start addresses, which the header already has
+            0x03 | 0x05 => {}
+            _ => yeet!(error(&format!("unsupported record type 0x{kind:02x}"))),
+        }
+    }
+    yeet!(anyhow!("Intel HEX: missing the end-of-file record"))
+}
+
+/// `text` is after the `v2.0 raw` line. Values are in hex, and `n*value`
+/// repeats `value` `n` times.
+fn parse_logisim(text: &str) -> anyhow::Result<Vec<u8>> {
+    let mut binary = Vec::new();
+    for line in text.lines() {
+        let line = line.split('#').next().unwrap();
+        for token in line.split_whitespace() {
+            let error = || anyhow!("Logisim image: invalid value `{token}`");
+            let (count, value) = match token.split_once('*') {
+                Some((n, x)) => (n.parse::<usize>().map_err(|_| error())?, x),
+                None => (1, token),
+            };
+            let value = u8::from_str_radix(value, 16).map_err(|_| error())?;
+            if count > MAX_SIZE - binary.len() {
+                yeet!(anyhow!("Logisim image: beyond the 16-bit address space"));
+            }
+            binary.extend(std::iter::repeat_n(value, count));
+        }
+    }
+    Ok(binary)
+}
+
+/// Whitespace-separated hex bytes, optionally with the `0x` prefix, and `#`
+/// and `//` comments. `@address` moves to `address`, like in `$readmemh`.
+fn parse_hex_text(text: &str) -> anyhow::Result<Vec<u8>> {
+    let mut binary = Vec::new();
+    let mut address = 0;
+    for line in text.lines() {
+        let line = line.split("//").next().unwrap();
+        let line = line.split('#').next().unwrap();
+        for token in line.split_whitespace() {
+            let error = || anyhow!("Invalid byte in the program: `{token}`");
+            if let Some(x) = token.strip_prefix('@') {
+                address = usize::from_str_radix(x, 16).map_err(|_| error())?;
+                continue;
+            }
+            if address >= MAX_SIZE {
+                yeet!(anyhow!("`{token}` is beyond the 16-bit address space"));
+            }
+            let digits = token.strip_prefix("0x").unwrap_or(token);
+            let value = u8::from_str_radix(digits, 16).map_err(|_| error())?;
+            if binary.len() <= address {
+                binary.resize(address + 1, 0);
+            }
+            binary[address] = value;
+            address += 1;
+        }
+    }
+    if binary.is_empty() {
+        yeet!(anyhow!("Unknown program format"));
+    }
+    Ok(binary)
+}
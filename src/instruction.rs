@@ -2,7 +2,7 @@ use crate::parse_u8_literal;
 use anyhow::anyhow;
 use num_enum::TryFromPrimitive;
 use std::str::FromStr;
-use strum_macros::EnumString;
+use strum_macros::{Display, EnumString};
 
 pub const COPY_STATIC_HEADER: u8 = 0b00000001;
 
@@ -18,8 +18,8 @@ pub const COPY_STATIC_HEADER: u8 = 0b00000001;
 /// - TTT: Type
 /// - SSS: Subtype
 #[repr(u8)]
-#[derive(Debug, Copy, Clone, Eq, PartialEq, EnumString, TryFromPrimitive)]
-#[strum(ascii_case_insensitive)]
+#[derive(Debug, Copy, Clone, Eq, PartialEq, EnumString, Display, TryFromPrimitive)]
+#[strum(ascii_case_insensitive, serialize_all = "lowercase")]
 pub enum Opcode {
     /* Compute */
     Add = 0b00001000,
@@ -109,6 +109,32 @@ pub enum OperandSymbol {
     Fss = 15,
 }
 
+impl OperandSymbol {
+    /// Name in the assembly. `in` and `out` share the same code, so it depends
+    /// on whether the operand is the destination.
+    pub fn asm_name(&self, destination: bool) -> &'static str {
+        match self {
+            OperandSymbol::R0 => "r0",
+            OperandSymbol::R1 => "r1",
+            OperandSymbol::R2 => "r2",
+            OperandSymbol::R3 => "r3",
+            OperandSymbol::R4 => "r4",
+            OperandSymbol::R5 => "r5",
+            OperandSymbol::R6 => "r6",
+            OperandSymbol::R7 => "r7",
+            OperandSymbol::R8 => "r8",
+            OperandSymbol::R9 => "r9",
+            OperandSymbol::R10 => "r10",
+            OperandSymbol::R11 => "r11",
+            OperandSymbol::InOut if destination => "out",
+            OperandSymbol::InOut => "in",
+            OperandSymbol::Aor => "aor",
+            OperandSymbol::Azr => "azr",
+            OperandSymbol::Fss => "fss",
+        }
+    }
+}
+
 #[derive(Debug, Copy, Clone, Eq, PartialEq)]
 pub enum Operand {
     Immediate(u8),
@@ -223,8 +249,61 @@ impl Opcode {
             Opcode::CAdd => (3, [1, 2, 3]),
             Opcode::Anc => (3, [1, 2, 3]),
             Opcode::Snc => (3, [1, 2, 3]),
-            Opcode::Mvc => (1, [0, 0, 3]),
+            Opcode::Mvc => (1, [0, 0, 1]),
+        }
+    }
+
+    /// Number of operands in the assembly form.
+    ///
+    /// `jamv` and `call` take one label operand, which is encoded as two bytes.
+    pub fn asm_operand_count(&self) -> usize {
+        match self {
+            Opcode::JumpAddrMove | Opcode::Call => 1,
+            _ => self.binary_asm_indices_mapping().0,
+        }
+    }
+
+    /// Index of the instruction byte naming the destination register, if any.
+    pub fn destination_index(&self) -> Option<usize> {
+        match self {
+            Opcode::Load => Some(2),
+            Opcode::Pop | Opcode::FPop => Some(1),
+            // the last two bytes are the address
+            Opcode::Call | Opcode::JumpAddrMove => None,
+            _ if self.binary_asm_indices_mapping().1[2] != 0 => Some(3),
+            _ => None,
+        }
+    }
+
+    /// Whether instruction byte `index` (1 or 2) is fetched as a source operand.
+    pub fn reads_operand(&self, index: usize) -> bool {
+        self.binary_asm_indices_mapping().1[index - 1] != 0
+            && self.destination_index() != Some(index)
+    }
+
+    /// The reverse of [`Opcode::binary`]. Operands are returned in the
+    /// assembly order.
+    ///
+    /// Register operands with invalid codes are returned as immediates.
+    /// For `jamv` and `call`, the two address bytes are returned as immediates.
+    pub fn decode_operands(&self, inst: &[u8; 4]) -> Vec<Operand> {
+        if let Opcode::JumpAddrMove | Opcode::Call = self {
+            return vec![Operand::Immediate(inst[2]), Operand::Immediate(inst[3])];
         }
+        let (count, mapping) = self.binary_asm_indices_mapping();
+        let imm1 = inst[0] & 0b10000000 != 0;
+        let imm2 = inst[0] & 0b01000000 != 0;
+        (1..=count)
+            .map(|asm_index| {
+                let inst_index = mapping.iter().position(|&x| x == asm_index).unwrap() + 1;
+                let value = inst[inst_index];
+                let immediate = (inst_index == 1 && imm1) || (inst_index == 2 && imm2);
+                match OperandSymbol::try_from(value) {
+                    Ok(x) if !immediate => Operand::Symbol(x),
+                    _ => Operand::Immediate(value),
+                }
+            })
+            .collect()
     }
 
     pub fn binary(&self, operands: &[Operand]) -> anyhow::Result<[u8; 4]> {
@@ -0,0 +1,164 @@
+//! Where the program input comes from and its output goes to.
+//!
+//! The [`Emulator`](crate::emulator::Emulator) owns one [`InputSource`],
+//! after its queued input, and one [`OutputSink`], which by default is a
+//! `Vec<u8>` collecting all the output:
+//!
+//! ```no_run
+//! # use leg_cpu_emulator::emulator::Emulator;
+//! # use leg_cpu_emulator::io::RingBuffer;
+//! # let binary = Vec::new();
+//! let output = Emulator::new(binary.clone())?.run_to_halt()?;
+//!
+//! // only the last 64 bytes, with the cycles they're written in
+//! let mut emulator = Emulator::new(binary)?;
+//! emulator.set_output(RingBuffer::new(64)).run(None)?;
+//! let last = emulator.output::<RingBuffer>().unwrap().entries();
+//! # anyhow::Ok(())
+//! ```
+
+use std::any::Any;
+use std::collections::VecDeque;
+use std::fmt::{self, Debug, Formatter};
+use std::io::{self, Read, Stdout, Write};
+
+pub trait InputSource {
+    /// The next byte, or `None` at the end of the input. May block.
+    fn read_byte(&mut self) -> io::Result<Option<u8>>;
+}
+
+pub trait OutputSink: Any + Debug {
+    /// `cycle` is the one `byte` is written in, counted from 1.
+    fn write(&mut self, byte: u8, cycle: u64) -> io::Result<()>;
+}
+
+/// Collects the output in a `Vec<u8>`.
+impl Default for Box<dyn OutputSink> {
+    fn default() -> Self {
+        Box::new(Vec::<u8>::new())
+    }
+}
+
+impl InputSource for VecDeque<u8> {
+    fn read_byte(&mut self) -> io::Result<Option<u8>> {
+        Ok(self.pop_front())
+    }
+}
+
+/// Input read byte by byte from a [`Read`], so nothing is consumed before the
+/// program asks for it.
+pub struct Reader<R>(pub R);
+
+impl<R: Read> InputSource for Reader<R> {
+    fn read_byte(&mut self) -> io::Result<Option<u8>> {
+        let mut byte = 0;
+        loop {
+            match self.0.read(std::slice::from_mut(&mut byte)) {
+                Ok(0) => return Ok(None),
+                Ok(_) => return Ok(Some(byte)),
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => return Err(e),
+            }
+        }
+    }
+}
+
+impl OutputSink for Vec<u8> {
+    fn write(&mut self, byte: u8, _cycle: u64) -> io::Result<()> {
+        self.push(byte);
+        Ok(())
+    }
+}
+
+/// Output written to a [`Write`], flushed after each byte.
+pub struct Writer<W>(pub W);
+
+/// A [`Writer`] to stdout.
+pub fn stdout() -> Writer<Stdout> {
+    Writer(io::stdout())
+}
+
+impl<W: Write + 'static> OutputSink for Writer<W> {
+    fn write(&mut self, byte: u8, _cycle: u64) -> io::Result<()> {
+        self.0.write_all(&[byte])?;
+        self.0.flush()
+    }
+}
+
+impl<W> Debug for Writer<W> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.write_str("Writer")
+    }
+}
+
+/// Input from a `FnMut() -> Option<u8>`, or output to a `FnMut(u8, u64)`
+/// taking the byte and its cycle.
+pub struct Callback<F>(pub F);
+
+impl<F: FnMut() -> Option<u8>> InputSource for Callback<F> {
+    fn read_byte(&mut self) -> io::Result<Option<u8>> {
+        Ok((self.0)())
+    }
+}
+
+impl<F: FnMut(u8, u64) + 'static> OutputSink for Callback<F> {
+    fn write(&mut self, byte: u8, cycle: u64) -> io::Result<()> {
+        (self.0)(byte, cycle);
+        Ok(())
+    }
+}
+
+impl<F> Debug for Callback<F> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.write_str("Callback")
+    }
+}
+
+/// Keeps the last `capacity` output bytes, with the cycles they're written in.
+#[derive(Debug, Clone)]
+pub struct RingBuffer {
+    capacity: usize,
+    entries: VecDeque<Timestamped>,
+    /// Number of bytes dropped to make room.
+    pub dropped: u64,
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct Timestamped {
+    pub cycle: u64,
+    pub byte: u8,
+}
+
+impl RingBuffer {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            entries: VecDeque::with_capacity(capacity),
+            dropped: 0,
+        }
+    }
+
+    /// From the oldest.
+    pub fn entries(&self) -> &VecDeque<Timestamped> {
+        &self.entries
+    }
+
+    pub fn bytes(&self) -> Vec<u8> {
+        self.entries.iter().map(|x| x.byte).collect()
+    }
+}
+
+impl OutputSink for RingBuffer {
+    fn write(&mut self, byte: u8, cycle: u64) -> io::Result<()> {
+        if self.capacity == 0 {
+            self.dropped += 1;
+            return Ok(());
+        }
+        if self.entries.len() == self.capacity {
+            self.entries.pop_front();
+            self.dropped += 1;
+        }
+        self.entries.push_back(Timestamped { cycle, byte });
+        Ok(())
+    }
+}
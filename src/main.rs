@@ -1,20 +1,120 @@
 #![feature(yeet_expr)]
 
 use clap::Parser;
+use leg_cpu_emulator::assembler::debug_info::{DebugInfo, DEBUG_INFO_EXTENSION};
+use leg_cpu_emulator::assembler::diagnostic::Diagnostics;
 use leg_cpu_emulator::assembler::Assembler;
-use leg_cpu_emulator::emulator::Emulator;
+use leg_cpu_emulator::debugger::{Command as DebuggerCommand, Debugger};
+use leg_cpu_emulator::device::{Display, Frame, Random, Timer, MAX_SCALE};
+use leg_cpu_emulator::disassembler::disassemble;
+use leg_cpu_emulator::emulator::{Emulator, InputPolicy, StopReason};
+use leg_cpu_emulator::image::{self, ImageFormat};
+use leg_cpu_emulator::parse_u8_literal;
+use leg_cpu_emulator::stack::{OverflowBehavior, STACK_DEPTH};
+use leg_cpu_emulator::trace::Trace;
+use std::cell::RefCell;
 use std::fs::File;
 use std::io;
-use std::io::{stdin, stdout, Read, Write};
-use std::path::PathBuf;
+use std::io::{stdin, stdout, BufRead, Read, Write};
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::str::FromStr;
+use strum_macros::EnumString;
 use yeet_ops::yeet;
 
 #[derive(clap::Parser)]
+#[command(args_conflicts_with_subcommands = true, subcommand_negates_reqs = true)]
+struct Cli {
+    #[command(subcommand)]
+    command: Option<Command>,
+    #[command(flatten)]
+    args: Args,
+}
+
+#[derive(clap::Subcommand)]
+enum Command {
+    /// Debug a program interactively.
+    Debug(DebugArgs),
+    /// Record, print or compare execution traces.
+    #[command(subcommand)]
+    Trace(TraceCommand),
+    /// Disassemble a program binary.
+    Disasm {
+        /// Path to the binary file.
+        binary: PathBuf,
+        /// Path to the output file. Prints to stdout if not given.
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+    },
+}
+
+#[derive(clap::Subcommand)]
+enum TraceCommand {
+    /// Run a program and record its execution trace.
+    Record {
+        /// Path to the source file (.asm/.bin).
+        source: PathBuf,
+        /// Path to the program input.
+        #[arg(short, long)]
+        input: Option<PathBuf>,
+        /// Path to the trace file. Defaults to the source path with extension .trace.
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+        /// Stop after this many CPU cycles if the program hasn't halted.
+        #[arg(long)]
+        max_cycles: Option<u64>,
+        #[command(flatten)]
+        emulator_args: EmulatorArgs,
+        #[command(flatten)]
+        display_args: DisplayArgs,
+    },
+    /// Print a trace file.
+    Show {
+        trace: PathBuf,
+        /// The traced program (.asm/.bin), for showing source lines.
+        #[arg(short, long)]
+        source: Option<PathBuf>,
+    },
+    /// Find the first tick where two traces diverge.
+    Diff {
+        trace1: PathBuf,
+        trace2: PathBuf,
+        /// The traced program (.asm/.bin), for showing source lines.
+        #[arg(short, long)]
+        source: Option<PathBuf>,
+        /// Number of preceding ticks to print as context.
+        #[arg(short, long, default_value_t = 5)]
+        context: usize,
+    },
+}
+
+#[derive(clap::Args)]
+struct DebugArgs {
+    /// Path to the source file (.asm/.bin).
+    ///
+    /// For .bin files, labels and source lines are read from the .dbg
+    /// sidecar file if there's one.
+    source: PathBuf,
+    /// Path to the program input.
+    #[arg(short, long)]
+    input: Option<PathBuf>,
+    /// Set breakpoints before starting. Either label names or addresses.
+    #[arg(short, long)]
+    breakpoint: Vec<String>,
+    #[command(flatten)]
+    emulator_args: EmulatorArgs,
+}
+
+#[derive(clap::Args)]
 struct Args {
     /// Path to the source file.
     ///
     /// The source file is of the two filename extensions: .asm/.bin
-    source: PathBuf,
+    ///
+    /// The program images written by `--out-type` (.hex/.img/.txt/.mem) can be
+    /// run like .bin files.
+    #[arg(required = true)]
+    source: Option<PathBuf>,
     /// Path to the output file.
     ///
     /// If no output file is specified, derive from the input file.
@@ -34,6 +134,178 @@ struct Args {
     /// Read program input from stdin.
     #[arg(long)]
     stdin: bool,
+    /// Pass each keypress to the program as it's typed, without echoing it.
+    /// Implies `--stdin`. Ctrl-C still interrupts, and the terminal is restored.
+    #[arg(long)]
+    raw: bool,
+    /// Also write the debug info file (.dbg) next to the output file.
+    #[arg(short = 'g', long)]
+    debug_info: bool,
+    /// Give up running after this many CPU cycles if the program hasn't halted.
+    #[arg(long)]
+    max_cycles: Option<u64>,
+    #[command(flatten)]
+    emulator_args: EmulatorArgs,
+    #[command(flatten)]
+    display_args: DisplayArgs,
+}
+
+#[derive(clap::Args)]
+struct EmulatorArgs {
+    /// Fault on illegal instructions, invalid registers, PC out of the program,
+    /// stack underflows/overflows and division by zero, instead of tolerating
+    /// them like the game hardware.
+    #[arg(long)]
+    strict: bool,
+    /// Number of entries of each stack.
+    #[arg(long, default_value_t = STACK_DEPTH)]
+    stack_depth: usize,
+    /// What a full stack does on push and an empty one on pop:
+    /// wrap, fault or saturate.
+    #[arg(long, default_value_t = OverflowBehavior::Wrap)]
+    stack_overflow: OverflowBehavior,
+    /// What reading `in` past the end of the input does: zero (gives 0),
+    /// block (stops the run) or fault.
+    #[arg(long, default_value_t = InputPolicy::Zero)]
+    on_input_end: InputPolicy,
+    /// Attach a memory-mapped device: timer, random or display, like
+    /// `timer@0xf0`.
+    #[arg(long = "device", value_name = "KIND@ADDRESS")]
+    devices: Vec<DeviceArg>,
+}
+
+impl EmulatorArgs {
+    fn configure(&self, emulator: &mut Emulator) -> anyhow::Result<()> {
+        if self.stack_depth == 0 {
+            yeet!(anyhow::anyhow!("Stack depth must not be zero"));
+        }
+        emulator
+            .set_strict(self.strict)
+            .set_stacks(self.stack_depth, self.stack_overflow)
+            .set_input_policy(self.on_input_end);
+        for x in &self.devices {
+            match x.kind {
+                DeviceKind::Timer => emulator.attach(x.address, Timer::default())?,
+                DeviceKind::Random => emulator.attach(x.address, Random::default())?,
+                DeviceKind::Display => emulator.attach(x.address, Display::default())?,
+            };
+        }
+        Ok(())
+    }
+}
+
+#[derive(Clone, Debug, Copy, EnumString)]
+#[strum(serialize_all = "lowercase")]
+enum DeviceKind {
+    Timer,
+    Random,
+    /// 32x32 pixels.
+    Display,
+}
+
+#[derive(clap::Args)]
+struct DisplayArgs {
+    /// Write the final frame of the display device to this file: .png, .ppm,
+    /// or .txt in ASCII.
+    #[arg(long)]
+    snapshot: Option<PathBuf>,
+    /// Also write every Nth presented frame, numbered, like `frame-0002.png`
+    /// for the snapshot path `frame.png`.
+    #[arg(long, requires = "snapshot")]
+    snapshot_every: Option<u32>,
+    /// Size of a pixel in the PNG and PPM snapshots, up to 16.
+    #[arg(
+        long,
+        default_value_t = 8,
+        value_parser = clap::value_parser!(u32).range(1..=MAX_SCALE as i64)
+    )]
+    snapshot_scale: u32,
+    /// Print the final frame of the display in ASCII.
+    #[arg(long)]
+    show_display: bool,
+}
+
+impl DisplayArgs {
+    /// Makes the display write the snapshots as they're taken. The returned
+    /// cell keeps the first error.
+    fn configure(&self, emulator: &mut Emulator) -> Rc<RefCell<anyhow::Result<()>>> {
+        let result = Rc::new(RefCell::new(Ok(())));
+        if let Some(x) = emulator.device_mut::<Display>() {
+            x.snapshot_every = self.snapshot_every;
+            if let Some(path) = self.snapshot.clone() {
+                let scale = self.snapshot_scale;
+                let result = Rc::clone(&result);
+                x.on_snapshot = Some(Box::new(move |frame| {
+                    if result.borrow().is_ok() {
+                        let numbered = numbered_path(&path, frame.number);
+                        *result.borrow_mut() = write_frame(&numbered, frame, scale);
+                    }
+                }));
+            }
+        }
+        result
+    }
+
+    /// Writes the final frame when the program stops.
+    fn write(&self, emulator: &Emulator) -> anyhow::Result<()> {
+        let Some(display) = emulator.device::<Display>() else {
+            if self.snapshot.is_some() || self.show_display {
+                yeet!(anyhow::anyhow!("No display device attached"));
+            }
+            return Ok(());
+        };
+        if self.show_display {
+            print!("{}", display.frame().to_ascii());
+        }
+        if let Some(path) = &self.snapshot {
+            write_frame(path, display.frame(), self.snapshot_scale)?;
+        }
+        Ok(())
+    }
+}
+
+/// `frame.png` becomes `frame-0004.png` for the frame 4.
+fn numbered_path(path: &Path, number: u32) -> PathBuf {
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    let mut name = format!("{stem}-{number:04}");
+    if let Some(extension) = path.extension() {
+        name = format!("{name}.{}", extension.to_string_lossy());
+    }
+    path.with_file_name(name)
+}
+
+fn write_frame(path: &Path, frame: &Frame, scale: u32) -> anyhow::Result<()> {
+    let data = match source_type(path).as_deref() {
+        Some("png") => frame.to_png(scale),
+        Some("ppm") => frame.to_ppm(scale),
+        Some("txt") => frame.to_ascii().into_bytes(),
+        _ => yeet!(anyhow::anyhow!("Snapshots can be .png, .ppm or .txt")),
+    };
+    std::fs::write(path, data)?;
+    Ok(())
+}
+
+#[derive(Clone, Debug)]
+struct DeviceArg {
+    kind: DeviceKind,
+    address: u8,
+}
+
+impl FromStr for DeviceArg {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (kind, address) = s
+            .split_once('@')
+            .ok_or_else(|| anyhow::anyhow!("Expected `<kind>@<address>`"))?;
+        Ok(Self {
+            kind: kind
+                .parse()
+                .map_err(|_| anyhow::anyhow!("Unknown device: {kind}"))?,
+            address: parse_u8_literal(address)
+                .ok_or_else(|| anyhow::anyhow!("Invalid address: {address}"))?,
+        })
+    }
 }
 
 #[derive(clap::ValueEnum, Clone, Debug, Copy)]
@@ -42,6 +314,34 @@ enum OutputType {
     CommentedHex,
     #[value(alias = "bin")]
     Binary,
+    /// Addresses, bytes and source lines, with the symbol table and the RAM
+    /// usage.
+    #[value(alias = "lst")]
+    Listing,
+    #[value(alias = "ihex")]
+    IntelHex,
+    /// Logisim-evolution ROM image (v2.0 raw).
+    Logisim,
+    /// One hex byte per line.
+    HexLines,
+    /// Verilog `$readmemh` file.
+    Verilog,
+    /// To paste into the program component of Turing Complete.
+    #[value(alias = "tc")]
+    TuringComplete,
+}
+
+impl OutputType {
+    fn image_format(self) -> Option<ImageFormat> {
+        match self {
+            OutputType::CommentedHex | OutputType::Binary | OutputType::Listing => None,
+            OutputType::IntelHex => Some(ImageFormat::IntelHex),
+            OutputType::Logisim => Some(ImageFormat::Logisim),
+            OutputType::HexLines => Some(ImageFormat::HexLines),
+            OutputType::Verilog => Some(ImageFormat::Verilog),
+            OutputType::TuringComplete => Some(ImageFormat::TuringComplete),
+        }
+    }
 }
 
 impl Default for OutputType {
@@ -51,27 +351,36 @@ impl Default for OutputType {
 }
 
 fn main() -> anyhow::Result<()> {
-    let args = Args::parse();
-    let mut source_file = File::open(&args.source)?;
-
-    let program_in = if args.stdin {
-        read_to_vec(stdin())?
-    } else {
-        match args.input {
-            None => {
-                vec![]
-            }
-            Some(path) => read_to_vec(File::open(path)?)?,
-        }
+    let cli = Cli::parse();
+    let result = match cli.command {
+        Some(Command::Debug(args)) => debug(args),
+        Some(Command::Trace(command)) => trace(command),
+        Some(Command::Disasm { binary, output }) => disasm(&binary, output),
+        None => assemble_or_run(cli.args),
     };
-
-    match args
-        .source
-        .extension()
-        .and_then(|x| x.to_str())
-        .map(|x| x.to_lowercase())
-        .as_deref()
+    if let Some(diagnostics) = result
+        .as_ref()
+        .err()
+        .and_then(|e| e.downcast_ref::<Diagnostics>())
     {
+        // already rendered in the rustc style; don't decorate it more
+        eprintln!("{diagnostics}");
+        std::process::exit(1);
+    }
+    result
+}
+
+fn assemble_or_run(args: Args) -> anyhow::Result<()> {
+    let source = args.source.expect("required by clap");
+    let mut source_file = File::open(&source)?;
+
+    let input = InputArgs {
+        path: args.input,
+        stdin: args.stdin || args.raw,
+        raw: args.raw,
+    };
+
+    match source_type(&source).as_deref() {
         Some("asm") => {
             let mut code = String::new();
             source_file.read_to_string(&mut code)?;
@@ -79,25 +388,36 @@ fn main() -> anyhow::Result<()> {
                 Some(x) => x,
                 None => match args.out_type.unwrap_or_default() {
                     OutputType::CommentedHex => {
-                        let mut path = args.source.clone();
+                        let mut path = source.clone();
                         path.set_extension("txt");
                         path
                     }
                     OutputType::Binary => {
-                        let mut path = args.source.clone();
+                        let mut path = source.clone();
                         path.set_extension("bin");
                         path
                     }
+                    OutputType::Listing => {
+                        let mut path = source.clone();
+                        path.set_extension("lst");
+                        path
+                    }
+                    x => {
+                        let mut path = source.clone();
+                        path.set_extension(x.image_format().unwrap().extension());
+                        path
+                    }
                 },
             };
 
-            let target = Assembler::new(code)?.assemble();
+            let target =
+                Assembler::with_file_name(code, source.display().to_string())?.assemble()?;
 
             if args.run {
                 // transparent-run mode. do not write to file
-                Emulator::new(target.binary.merge())?
-                    .set_input(program_in)
-                    .run_to_halt_with_print()?;
+                let mut emulator = Emulator::new(target.binary.merge())?;
+                args.emulator_args.configure(&mut emulator)?;
+                input.run(&mut emulator, args.max_cycles, &args.display_args)?;
             } else {
                 let out: &mut dyn Write = if args.stdout {
                     &mut stdout()
@@ -111,19 +431,341 @@ fn main() -> anyhow::Result<()> {
                     OutputType::Binary => {
                         out.write_all(&target.binary.merge())?;
                     }
+                    OutputType::Listing => {
+                        out.write_all(target.listing.as_bytes())?;
+                    }
+                    x => {
+                        let image = x.image_format().unwrap().write(&target.binary);
+                        out.write_all(image.as_bytes())?;
+                    }
+                }
+                if args.debug_info {
+                    let path = out_file.with_extension(DEBUG_INFO_EXTENSION);
+                    std::fs::write(path, target.debug_info.to_string())?;
                 }
             }
         }
-        Some("bin") => {
+        Some(x) if PROGRAM_EXTENSIONS.contains(&x) => {
             // execute the program
             let mut bin = Vec::new();
             source_file.read_to_end(&mut bin)?;
-            Emulator::new(bin)?.set_input(program_in).run_to_halt_with_print()?;
+            let mut emulator = Emulator::new(image::load(&bin)?)?;
+            args.emulator_args.configure(&mut emulator)?;
+            input.run(&mut emulator, args.max_cycles, &args.display_args)?;
+        }
+        _ => yeet!(anyhow::anyhow!(
+            "Cannot determine input file type from the name extension"
+        )),
+    };
+    Ok(())
+}
+
+/// Where the program input of a run comes from.
+struct InputArgs {
+    path: Option<PathBuf>,
+    stdin: bool,
+    raw: bool,
+}
+
+impl InputArgs {
+    /// Runs with the input, which is read as the program asks for it.
+    fn run(
+        &self,
+        emulator: &mut Emulator,
+        max_cycles: Option<u64>,
+        display_args: &DisplayArgs,
+    ) -> anyhow::Result<()> {
+        if self.stdin {
+            emulator.set_input_reader(stdin());
+        } else if let Some(path) = &self.path {
+            emulator.set_input_reader(File::open(path)?);
+        }
+        let _raw_terminal = if self.raw {
+            Some(RawTerminal::enable()?)
+        } else {
+            None
+        };
+        run_with_print(emulator, max_cycles, display_args)
+    }
+}
+
+/// Turns off the line buffering and echo of the terminal on stdin until
+/// dropped.
+#[cfg(unix)]
+struct RawTerminal(libc::termios);
+
+/// The terminal settings to restore when a signal kills the process, which
+/// doesn't run `Drop`.
+#[cfg(unix)]
+static ORIGINAL_TERMIOS: std::sync::OnceLock<libc::termios> = std::sync::OnceLock::new();
+
+/// Signals from the terminal keys kept by [`RawTerminal`], and `kill`.
+#[cfg(unix)]
+const TERMINATING_SIGNALS: [libc::c_int; 3] = [libc::SIGINT, libc::SIGQUIT, libc::SIGTERM];
+
+#[cfg(unix)]
+extern "C" fn restore_terminal_and_reraise(signal: libc::c_int) {
+    // only async-signal-safe calls here
+    unsafe {
+        if let Some(x) = ORIGINAL_TERMIOS.get() {
+            libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, x);
+        }
+        libc::signal(signal, libc::SIG_DFL);
+        libc::raise(signal);
+    }
+}
+
+#[cfg(unix)]
+impl RawTerminal {
+    fn enable() -> anyhow::Result<Self> {
+        let fd = libc::STDIN_FILENO;
+        let mut original = std::mem::MaybeUninit::uninit();
+        // SAFETY: `original` is initialized if it succeeds
+        if unsafe { libc::tcgetattr(fd, original.as_mut_ptr()) } != 0 {
+            let error = io::Error::last_os_error();
+            yeet!(anyhow::anyhow!(
+                "Raw mode needs stdin to be a terminal: {error}"
+            ));
+        }
+        let original = unsafe { original.assume_init() };
+        let mut raw = original;
+        // keep ISIG, so Ctrl-C still works; the handler restores the terminal
+        // before the signal kills the process
+        raw.c_lflag &= !(libc::ICANON | libc::ECHO);
+        raw.c_cc[libc::VMIN] = 1;
+        raw.c_cc[libc::VTIME] = 0;
+        let _ = ORIGINAL_TERMIOS.set(original);
+        for x in TERMINATING_SIGNALS {
+            let handler = restore_terminal_and_reraise as extern "C" fn(libc::c_int);
+            unsafe { libc::signal(x, handler as libc::sighandler_t) };
+        }
+        if unsafe { libc::tcsetattr(fd, libc::TCSANOW, &raw) } != 0 {
+            yeet!(anyhow::Error::from(io::Error::last_os_error()));
+        }
+        Ok(Self(original))
+    }
+}
+
+#[cfg(unix)]
+impl Drop for RawTerminal {
+    fn drop(&mut self) {
+        unsafe {
+            libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &self.0);
+            for x in TERMINATING_SIGNALS {
+                libc::signal(x, libc::SIG_DFL);
+            }
+        }
+    }
+}
+
+#[cfg(not(unix))]
+struct RawTerminal;
+
+#[cfg(not(unix))]
+impl RawTerminal {
+    fn enable() -> anyhow::Result<Self> {
+        yeet!(anyhow::anyhow!("Raw mode is only supported on Unix"));
+    }
+}
+
+/// Runs the program and prints its output. Fails if it doesn't halt within `max_cycles`.
+fn run_with_print(
+    emulator: &mut Emulator,
+    max_cycles: Option<u64>,
+    display_args: &DisplayArgs,
+) -> anyhow::Result<()> {
+    let snapshot_result = display_args.configure(emulator);
+    emulator.set_output(leg_cpu_emulator::io::stdout());
+    let stop_reason = emulator.run(max_cycles);
+    // a snapshot may have failed before the run did, so report it first
+    snapshot_result.replace(Ok(()))?;
+    let stop_reason = stop_reason?;
+    display_args.write(emulator)?;
+    match stop_reason {
+        StopReason::Halted => Ok(()),
+        StopReason::BudgetExhausted => yeet!(anyhow::anyhow!(
+            "Program didn't halt within {} cycles (pc: 0x{:04x})",
+            emulator.cycles,
+            *emulator.pc
+        )),
+        StopReason::InputExhausted => yeet!(anyhow::anyhow!(
+            "Program is waiting for input (pc: 0x{:04x})",
+            *emulator.pc
+        )),
+        StopReason::Fault(x) => yeet!(anyhow::Error::from(x)),
+    }
+}
+
+/// Extensions of the program binaries, raw or in an [`ImageFormat`].
+const PROGRAM_EXTENSIONS: [&str; 5] = ["bin", "hex", "img", "txt", "mem"];
+
+fn source_type(path: &Path) -> Option<String> {
+    path.extension()
+        .and_then(|x| x.to_str())
+        .map(|x| x.to_lowercase())
+}
+
+/// Returns the program binary along with its debug info.
+fn load_program(path: &Path) -> anyhow::Result<(Vec<u8>, DebugInfo)> {
+    match source_type(path).as_deref() {
+        Some("asm") => {
+            let code = std::fs::read_to_string(path)?;
+            let target = Assembler::with_file_name(code, path.display().to_string())?.assemble()?;
+            Ok((target.binary.merge(), target.debug_info))
+        }
+        Some(x) if PROGRAM_EXTENSIONS.contains(&x) => {
+            let sidecar = path.with_extension(DEBUG_INFO_EXTENSION);
+            let debug_info = if sidecar.exists() {
+                std::fs::read_to_string(sidecar)?.parse()?
+            } else {
+                DebugInfo::default()
+            };
+            Ok((image::load(&std::fs::read(path)?)?, debug_info))
         }
         _ => yeet!(anyhow::anyhow!(
             "Cannot determine input file type from the name extension"
         )),
+    }
+}
+
+fn debug(args: DebugArgs) -> anyhow::Result<()> {
+    let (binary, debug_info) = load_program(&args.source)?;
+    let program_in = match args.input {
+        None => vec![],
+        Some(path) => read_to_vec(File::open(path)?)?,
+    };
+    let mut emulator = Emulator::new(binary)?;
+    emulator.set_input(program_in);
+    args.emulator_args.configure(&mut emulator)?;
+
+    let mut debugger = Debugger::new(emulator, debug_info);
+    for x in &args.breakpoint {
+        debugger.add_breakpoint(x)?;
+    }
+
+    // the program reads its input from `--input`, so stdin is free for the REPL
+    let mut stdout = stdout();
+    let mut lines = stdin().lock().lines();
+    let mut last_command = None;
+    loop {
+        write!(stdout, "(leg) ")?;
+        stdout.flush()?;
+        let Some(line) = lines.next() else {
+            break;
+        };
+        let line = line?;
+        let command = match line.trim() {
+            "" => match last_command.clone() {
+                Some(x) => x,
+                None => continue,
+            },
+            x => match x.parse::<DebuggerCommand>() {
+                Ok(x) => x,
+                Err(e) => {
+                    eprintln!("{e}");
+                    continue;
+                }
+            },
+        };
+        if command == DebuggerCommand::Quit {
+            break;
+        }
+
+        let result = debugger.execute(&command);
+        // print the program output first
+        stdout.write_all(&debugger.take_output())?;
+        match result {
+            Ok(x) => write!(stdout, "{x}")?,
+            Err(e) => eprintln!("{e}"),
+        }
+        last_command = Some(command);
+    }
+    Ok(())
+}
+
+fn trace(command: TraceCommand) -> anyhow::Result<()> {
+    let debug_info_of = |source: Option<PathBuf>| match source {
+        Some(x) => load_program(&x).map(|x| x.1),
+        None => Ok(DebugInfo::default()),
     };
+    match command {
+        TraceCommand::Record {
+            source,
+            input,
+            output,
+            max_cycles,
+            emulator_args,
+            display_args,
+        } => {
+            let (binary, _) = load_program(&source)?;
+            let program_in = match input {
+                None => vec![],
+                Some(path) => read_to_vec(File::open(path)?)?,
+            };
+            let mut emulator = Emulator::new(binary)?;
+            emulator.set_input(program_in).enable_trace();
+            emulator_args.configure(&mut emulator)?;
+            let result = run_with_print(&mut emulator, max_cycles, &display_args);
+
+            // also keep the trace of a runaway program
+            let output = output.unwrap_or_else(|| source.with_extension("trace"));
+            let trace = emulator.trace.take().unwrap();
+            trace.write_to(io::BufWriter::new(File::create(output)?))?;
+            result?;
+        }
+        TraceCommand::Show { trace, source } => {
+            let debug_info = debug_info_of(source)?;
+            let trace = Trace::read_from(File::open(trace)?)?;
+            let mut stdout = io::BufWriter::new(stdout());
+            for i in 0..trace.entries.len() {
+                writeln!(stdout, "{}", trace.format_entry(i, &debug_info))?;
+            }
+        }
+        TraceCommand::Diff {
+            trace1,
+            trace2,
+            source,
+            context,
+        } => {
+            let debug_info = debug_info_of(source)?;
+            let trace1 = Trace::read_from(File::open(trace1)?)?;
+            let trace2 = Trace::read_from(File::open(trace2)?)?;
+            let Some(index) = trace1.first_divergence(&trace2) else {
+                println!("Traces are identical ({} ticks)", trace1.entries.len());
+                return Ok(());
+            };
+            println!("Traces diverge at tick #{index}");
+            for i in index.saturating_sub(context)..index {
+                println!("  {}", trace1.format_entry(i, &debug_info));
+            }
+            for (name, trace) in [("1", &trace1), ("2", &trace2)] {
+                match trace.entries.get(index) {
+                    Some(_) => println!("{name} {}", trace.format_entry(index, &debug_info)),
+                    None => println!("{name} <end of trace>"),
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+fn disasm(binary: &Path, output: Option<PathBuf>) -> anyhow::Result<()> {
+    let disassembly = disassemble(&std::fs::read(binary)?)?;
+    match output {
+        Some(x) => std::fs::write(x, &disassembly.code)?,
+        None => print!("{}", disassembly.code),
+    }
+    if !disassembly.inexact.is_empty() {
+        let addresses = disassembly
+            .inexact
+            .iter()
+            .map(|x| format!("0x{x:04x}"))
+            .collect::<Vec<_>>();
+        eprintln!(
+            "warning: bytes at {} are not reproduced by the assembly",
+            addresses.join(", ")
+        );
+    }
     Ok(())
 }
 
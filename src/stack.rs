@@ -0,0 +1,168 @@
+//! Fixed-depth hardware stacks.
+
+use strum_macros::{Display, EnumString};
+
+/// Default depth of the hardware stacks, as the game circuit.
+pub const STACK_DEPTH: usize = 256;
+
+/// What a full stack does on `push`, and an empty one on `pop`.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Default, EnumString, Display)]
+#[strum(ascii_case_insensitive, serialize_all = "lowercase")]
+pub enum OverflowBehavior {
+    /// The stack pointer wraps around, like the game circuit. Pushing onto a full
+    /// stack overwrites the bottom entry, and popping an empty one reads the top
+    /// of the storage.
+    #[default]
+    Wrap,
+    /// Both are errors.
+    Fault,
+    /// Pushing onto a full stack drops the value, and popping an empty one gives 0.
+    Saturate,
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum StackError {
+    Overflow,
+    Underflow,
+}
+
+/// A stack of `depth` entries with an explicit stack pointer.
+#[derive(Debug, Clone)]
+pub struct BoundedStack<T> {
+    storage: Vec<T>,
+    /// Index of the next free slot. With [`OverflowBehavior::Wrap`] it's kept
+    /// below `depth`.
+    pointer: usize,
+    /// Number of entries pushed and not popped yet, at most `depth`.
+    len: usize,
+    behavior: OverflowBehavior,
+}
+
+impl<T: Copy + Default> Default for BoundedStack<T> {
+    fn default() -> Self {
+        Self::new(STACK_DEPTH, OverflowBehavior::default())
+    }
+}
+
+impl<T: Copy + Default> BoundedStack<T> {
+    pub fn new(depth: usize, behavior: OverflowBehavior) -> Self {
+        assert!(depth > 0, "Stack depth must not be zero");
+        Self {
+            storage: vec![T::default(); depth],
+            pointer: 0,
+            len: 0,
+            behavior,
+        }
+    }
+
+    pub fn depth(&self) -> usize {
+        self.storage.len()
+    }
+
+    pub fn behavior(&self) -> OverflowBehavior {
+        self.behavior
+    }
+
+    pub fn pointer(&self) -> usize {
+        self.pointer
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn is_full(&self) -> bool {
+        self.len == self.depth()
+    }
+
+    pub fn push(&mut self, value: T) -> Result<(), StackError> {
+        if self.is_full() {
+            match self.behavior {
+                OverflowBehavior::Wrap => {}
+                OverflowBehavior::Fault => return Err(StackError::Overflow),
+                OverflowBehavior::Saturate => return Ok(()),
+            }
+        }
+        self.storage[self.pointer] = value;
+        self.pointer += 1;
+        if self.behavior == OverflowBehavior::Wrap {
+            self.pointer %= self.depth();
+        }
+        self.len = (self.len + 1).min(self.depth());
+        Ok(())
+    }
+
+    pub fn pop(&mut self) -> Result<T, StackError> {
+        if self.is_empty() {
+            match self.behavior {
+                OverflowBehavior::Wrap => {}
+                OverflowBehavior::Fault => return Err(StackError::Underflow),
+                OverflowBehavior::Saturate => return Ok(T::default()),
+            }
+        }
+        self.pointer = (self.pointer + self.depth() - 1) % self.depth();
+        self.len = self.len.saturating_sub(1);
+        Ok(self.storage[self.pointer])
+    }
+
+    /// The live entries, from bottom to top.
+    pub fn iter(&self) -> impl DoubleEndedIterator<Item = &T> {
+        let bottom = (self.pointer + self.depth() - self.len) % self.depth();
+        (0..self.len).map(move |i| &self.storage[(bottom + i) % self.depth()])
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::stack::{BoundedStack, OverflowBehavior, StackError};
+
+    fn full_stack(behavior: OverflowBehavior) -> BoundedStack<u8> {
+        let mut stack = BoundedStack::new(3, behavior);
+        for x in 1..=3 {
+            stack.push(x).unwrap();
+        }
+        assert!(stack.is_full());
+        stack
+    }
+
+    #[test]
+    fn wrap() {
+        let mut stack = full_stack(OverflowBehavior::Wrap);
+        assert_eq!(stack.pointer(), 0);
+        stack.push(4).unwrap();
+        assert_eq!(stack.pointer(), 1);
+        assert_eq!(stack.iter().copied().collect::<Vec<_>>(), [2, 3, 4]);
+        assert_eq!(stack.pop(), Ok(4));
+        assert_eq!(stack.pop(), Ok(3));
+        assert_eq!(stack.pop(), Ok(2));
+        // reads the stale storage
+        assert_eq!(stack.pop(), Ok(4));
+        assert_eq!(stack.pointer(), 0);
+    }
+
+    #[test]
+    fn fault() {
+        let mut stack = full_stack(OverflowBehavior::Fault);
+        assert_eq!(stack.push(4), Err(StackError::Overflow));
+        for _ in 0..3 {
+            stack.pop().unwrap();
+        }
+        assert_eq!(stack.pop(), Err(StackError::Underflow));
+    }
+
+    #[test]
+    fn saturate() {
+        let mut stack = full_stack(OverflowBehavior::Saturate);
+        stack.push(4).unwrap();
+        assert_eq!(stack.pointer(), 3);
+        assert_eq!(stack.pop(), Ok(3));
+        stack.pop().unwrap();
+        stack.pop().unwrap();
+        assert_eq!(stack.pop(), Ok(0));
+        assert_eq!(stack.pointer(), 0);
+    }
+}
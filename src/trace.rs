@@ -0,0 +1,198 @@
+//! Per-tick execution trace of the [`Emulator`](crate::emulator::Emulator).
+//!
+//! ## File format
+//!
+//! The file starts with [`TRACE_MAGIC`], followed by fixed-length
+//! ([`RECORD_LENGTH`]-byte) records, one for each tick:
+//!
+//! | Bytes | Content                                                  |
+//! | ----- | -------------------------------------------------------- |
+//! | 0..2  | PC (little-endian)                                       |
+//! | 2..6  | Instruction bytes                                        |
+//! | 6..8  | Operand values                                           |
+//! | 8     | Flags, see below                                         |
+//! | 9..12 | Written location (two bytes, little-endian) and value    |
+//! | 12    | Input byte                                               |
+//! | 13    | Output byte                                              |
+//!
+//! Flags: bits 0-1 are the write kind (0: none, 1: register, 2: RAM,
+//! 3: jump address), bit 2 is set if the carry changed, bit 3 is the new carry,
+//! bit 4 is set if an input byte was read, and bit 5 is set if a byte was written out.
+
+use crate::assembler::debug_info::DebugInfo;
+use crate::instruction::Opcode;
+use anyhow::anyhow;
+use std::fmt::{self, Display, Formatter};
+use std::io;
+use std::io::{Read, Write};
+use yeet_ops::yeet;
+
+pub const TRACE_MAGIC: &[u8; 8] = b"LEGTRC01";
+pub const RECORD_LENGTH: usize = 14;
+
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub struct Trace {
+    pub entries: Vec<TraceEntry>,
+}
+
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub struct TraceEntry {
+    pub pc: u16,
+    pub inst: [u8; 4],
+    /// Values of the first and second operand, either immediates or fetched
+    /// from registers.
+    pub operands: [u8; 2],
+    pub write: Option<TraceWrite>,
+    /// The new carry flag, if it changed.
+    pub carry: Option<bool>,
+    pub input: Option<u8>,
+    pub output: Option<u8>,
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum TraceWrite {
+    /// Register code and the value.
+    Register(u8, u8),
+    /// RAM address and the value.
+    Ram(u8, u8),
+    JumpAddress(u16),
+}
+
+impl TraceEntry {
+    pub fn opcode(&self) -> Option<Opcode> {
+        Opcode::try_from(self.inst[0] & 0b00111111).ok()
+    }
+
+    fn encode(&self) -> [u8; RECORD_LENGTH] {
+        let mut record = [0_u8; RECORD_LENGTH];
+        record[0..2].copy_from_slice(&self.pc.to_le_bytes());
+        record[2..6].copy_from_slice(&self.inst);
+        record[6..8].copy_from_slice(&self.operands);
+
+        let mut flags = 0_u8;
+        let (kind, location, value) = match self.write {
+            None => (0, 0, 0),
+            Some(TraceWrite::Register(reg, value)) => (1, reg as u16, value),
+            Some(TraceWrite::Ram(addr, value)) => (2, addr as u16, value),
+            Some(TraceWrite::JumpAddress(addr)) => (3, addr, 0),
+        };
+        flags |= kind;
+        record[9..11].copy_from_slice(&location.to_le_bytes());
+        record[11] = value;
+        if let Some(carry) = self.carry {
+            flags |= 0b100 | (u8::from(carry) << 3);
+        }
+        if let Some(x) = self.input {
+            flags |= 0b10000;
+            record[12] = x;
+        }
+        if let Some(x) = self.output {
+            flags |= 0b100000;
+            record[13] = x;
+        }
+        record[8] = flags;
+        record
+    }
+
+    fn decode(record: &[u8; RECORD_LENGTH]) -> Self {
+        let flags = record[8];
+        let location = u16::from_le_bytes([record[9], record[10]]);
+        let value = record[11];
+        let write = match flags & 0b11 {
+            1 => Some(TraceWrite::Register(location as u8, value)),
+            2 => Some(TraceWrite::Ram(location as u8, value)),
+            3 => Some(TraceWrite::JumpAddress(location)),
+            _ => None,
+        };
+        Self {
+            pc: u16::from_le_bytes([record[0], record[1]]),
+            inst: record[2..6].try_into().unwrap(),
+            operands: [record[6], record[7]],
+            write,
+            carry: (flags & 0b100 != 0).then_some(flags & 0b1000 != 0),
+            input: (flags & 0b10000 != 0).then_some(record[12]),
+            output: (flags & 0b100000 != 0).then_some(record[13]),
+        }
+    }
+}
+
+impl Display for TraceEntry {
+    /// Formats like `0x0010 cp     [83000000] ops=00,00 r0<-0x00 carry=1 in=0x31 out=0x0a`.
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let mnemonic = match self.opcode() {
+            Some(x) => x.to_string(),
+            None => "???".into(),
+        };
+        write!(
+            f,
+            "0x{:04x} {mnemonic:<6} [{}] ops={:02x},{:02x}",
+            self.pc,
+            hex::encode(self.inst),
+            self.operands[0],
+            self.operands[1]
+        )?;
+        match self.write {
+            None => {}
+            Some(TraceWrite::Register(15, value)) => write!(f, " fss<-0x{value:02x}")?,
+            Some(TraceWrite::Register(reg, value)) => write!(f, " r{reg}<-0x{value:02x}")?,
+            Some(TraceWrite::Ram(addr, value)) => write!(f, " [0x{addr:02x}]<-0x{value:02x}")?,
+            Some(TraceWrite::JumpAddress(addr)) => write!(f, " jump_address<-0x{addr:04x}")?,
+        }
+        if let Some(x) = self.carry {
+            write!(f, " carry={}", u8::from(x))?;
+        }
+        if let Some(x) = self.input {
+            write!(f, " in=0x{x:02x}")?;
+        }
+        if let Some(x) = self.output {
+            write!(f, " out=0x{x:02x}")?;
+        }
+        Ok(())
+    }
+}
+
+impl Trace {
+    pub fn write_to(&self, mut writer: impl Write) -> io::Result<()> {
+        writer.write_all(TRACE_MAGIC)?;
+        for x in &self.entries {
+            writer.write_all(&x.encode())?;
+        }
+        Ok(())
+    }
+
+    pub fn read_from(mut reader: impl Read) -> anyhow::Result<Self> {
+        let mut data = Vec::new();
+        reader.read_to_end(&mut data)?;
+        let Some(records) = data.strip_prefix(TRACE_MAGIC) else {
+            yeet!(anyhow!("Not a trace file"));
+        };
+        let chunks = records.chunks_exact(RECORD_LENGTH);
+        if !chunks.remainder().is_empty() {
+            yeet!(anyhow!("Truncated trace file"));
+        }
+        let entries = chunks
+            .map(|x| TraceEntry::decode(x.try_into().unwrap()))
+            .collect();
+        Ok(Self { entries })
+    }
+
+    /// Index of the first tick where the two traces disagree, including
+    /// one trace ending earlier than the other.
+    pub fn first_divergence(&self, other: &Trace) -> Option<usize> {
+        let common = self.entries.len().min(other.entries.len());
+        (0..common)
+            .find(|&i| self.entries[i] != other.entries[i])
+            .or((self.entries.len() != other.entries.len()).then_some(common))
+    }
+
+    /// Formats the entry at `index`, with its source line if `debug_info`
+    /// covers it.
+    pub fn format_entry(&self, index: usize, debug_info: &DebugInfo) -> String {
+        let entry = &self.entries[index];
+        let mut line = format!("#{index:<6} {entry}");
+        if let Some(x) = debug_info.find(entry.pc) {
+            line.push_str(&format!("  ; {}:{}: {}", x.file, x.line, x.statement));
+        }
+        line
+    }
+}
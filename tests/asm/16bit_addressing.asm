@@ -1,4 +1,4 @@
-.entry _start ; For now the entrypoint address supports only 8bits still.
+.entry _start ; jumps to `start` through a trampoline
 
 .code
 _start:
@@ -0,0 +1,51 @@
+; A lookup table, a string pool and a stack area in separate data segments.
+; Prints the squares of 1-4, looked up in the table, as characters.
+
+.data 0x00
+squares [0, 1, 4, 9, 16] _
+
+.data 0x80 strings
+prefix 'squares:' prefix_len
+digits '0123456789'
+
+.data 0xe0 stack
+ss reserve 0x20
+
+.entry main
+
+.code
+main:
+    cp ss fss
+    cp 0 r0
+.prefix:
+    anc r0 prefix r1
+    ld r1 out
+    add r0 1 r0
+    jlt r0 prefix_len .prefix
+
+    cp 1 r0
+.square:
+    cp ' ' out
+    ld r0 r1 ; squares[r0]
+    fpush r1
+    call print
+    add r0 1 r0
+    jle r0 4 .square
+    halt
+
+; prints a number below 100
+print:
+    push r0
+    push r1
+    fpop r0
+    div r0 10 r1
+    jeq r1 0 1f
+    anc r1 digits r1
+    ld r1 out
+1:
+    mod r0 10 r0
+    anc r0 digits r0
+    ld r0 out
+    pop r1
+    pop r0
+    ret
@@ -0,0 +1,33 @@
+; draws a diagonal on the display at 0xf0, one pixel per frame
+
+.consts
+X 0xf0
+Y 0xf1
+COLOR 0xf2
+COMMAND 0xf3
+SIZE 8
+
+.entry start
+
+.code
+start:
+    st COMMAND 0 ; clear
+    cp 0 r0
+.loop:
+    st X r0
+    st Y r0
+    add r0 1 r1
+    st COLOR r1 ; the colors 1 to 8
+    st COMMAND 1 ; present
+    inc r0
+    jlt r0 SIZE .loop
+
+    ; a white border on the right
+    st X SIZE-1
+    cp 0 r0
+1:
+    st Y r0
+    st COLOR 15
+    inc r0
+    jlt r0 SIZE 1b
+    halt
@@ -0,0 +1,21 @@
+; Prints the numbers in `nums` with the shared routine in `lib/print.asm`.
+
+.include "lib/print.asm"
+.include ./lib/../lib/ascii.asm ; included once
+
+.data 0
+nums [0, 7, 42, 100, 255] len
+
+.entry main
+
+.code
+main:
+    cp 0 r0
+    loop:
+    ld r0 r1
+    fpush r1
+    call print_u8
+    add r0 1 r0
+    jamv loop
+    jplt r0 len
+    halt
@@ -0,0 +1,3 @@
+.consts
+NEW_LINE 10
+ZERO 0x30
@@ -0,0 +1,36 @@
+; Decimal printing routines.
+
+.include ascii.asm
+
+.code
+; Prints a u8 in decimal, followed by a new line.
+; args: num
+print_u8:
+    push r0
+    push r1
+    push r2
+    fpop r0
+    cp 0 r2 ; printed a digit
+
+    div r0 100 r1
+    jamv print_u8_tens
+    jpeq r1 0
+    add r1 ZERO out
+    cp 1 r2
+    print_u8_tens:
+    mod r0 100 r0
+    div r0 10 r1
+    jamv print_u8_tens_print
+    jpne r2 0
+    jamv print_u8_ones
+    jpeq r1 0
+    print_u8_tens_print:
+    add r1 ZERO out
+    print_u8_ones:
+    mod r0 10 r0
+    add r0 ZERO out
+    cp NEW_LINE out
+    pop r2
+    pop r1
+    pop r0
+    ret
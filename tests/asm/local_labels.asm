@@ -0,0 +1,68 @@
+; `selection_sort.asm`, then a reversal, written with local and anonymous labels
+
+.data 0
+data [14, 6, 3, 2, 1, 11, 15, 8, 13, 4, 12, 5, 7, 10, 9, 0] length
+
+.entry start
+
+.code
+; args: n
+f_sort:
+    push r0
+    fpop r0 ; n
+
+    cp 0 r2 ; i
+.outer:
+    cp r2 r4 ; min_idx = i;
+    cp r2 r1 ; j = i;
+.inner:
+    ld r1 r3
+    ld r4 r5
+    jamv 1f
+    jpge r3 r5
+    cp r1 r4
+1:
+    add r1 1 r1
+    jamv .inner
+    jplt r1 r0
+
+    jamv 1f
+    jpeq r4 r2
+    ld r4 r3
+    ld r2 r5
+    st r2 r3
+    st r4 r5
+1:
+    add r2 1 r2
+    jamv .outer
+    jplt r2 r0
+
+    pop r0
+    ret
+
+; reverses the first n bytes
+; args: n
+f_reverse:
+    fpop r1
+    sub r1 1 r1 ; j
+    cp 0 r0 ; i
+.loop:
+    jamv .end
+    jpge r0 r1
+    ld r0 r2
+    ld r1 r3
+    st r0 r3
+    st r1 r2
+    add r0 1 r0
+    sub r1 1 r1
+    jamv .loop
+    jp
+.end:
+    ret
+
+start:
+    fpush length
+    call f_sort
+    fpush length
+    call f_reverse
+    halt
@@ -0,0 +1,90 @@
+; `function_stack.asm` written with macros
+
+.macro call3 f a b c
+    fpush \c
+    fpush \b
+    fpush \a
+    call \f
+.endm
+
+.macro push2 a b
+    push \a
+    push \b
+.endm
+
+.macro save
+    push2 r0 r1
+    push2 r2 r3
+.endm
+
+.macro restore
+    pop r3
+    pop r2
+    pop r1
+    pop r0
+.endm
+
+; *(base + offset) = value, using `r3`
+.macro st_at base offset value
+    anc \base \offset r3
+    st r3 \value
+.endm
+
+; value = *(base + offset), using `r3`
+.macro ld_at base offset value
+    anc \base \offset r3
+    ld r3 \value
+.endm
+
+; increases `len` bytes from `addr`. `addr` is advanced to the end
+.macro inc_all addr len
+    add \addr \len \len
+    loop:
+    ld \addr r2
+    add r2 1 r2
+    st \addr r2
+    add \addr 1 \addr
+    jamv loop
+    jplt \addr \len
+.endm
+
+.data 0
+arr [1, 2, 3, 4, 5] length
+ss [] _ ; stack memory start
+
+.entry start
+
+.code
+start:
+    cp ss fss
+    call3 foo arr length 0
+    halt
+
+; args: arr, an, n. Stops at n=5
+foo:
+    save
+    cp fss r11
+    anc fss 4 fss
+
+    fpop r0 ; arr
+    fpop r1 ; an
+    fpop r2 ; n
+    jamv f1_end
+    jpeq r2 5
+
+    st_at r11 0 r0
+    st_at r11 1 r1
+    st_at r11 2 r2
+
+    inc_all r0 r1
+
+    ld_at r11 2 r0
+    add r0 1 r0
+    ld_at r11 1 r1
+    ld_at r11 0 r2
+    call3 foo r2 r1 r0
+
+    f1_end:
+    snc fss 4 fss
+    restore
+    ret
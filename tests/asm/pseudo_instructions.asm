@@ -0,0 +1,38 @@
+; Pseudo-instructions, writing their results to RAM
+
+.data 0
+results [] _
+
+.entry start
+
+.code
+start:
+    clr r0
+    mov 5 r1
+.count:
+    inc r0
+    dec r1
+    jne r1 0 .count
+    st 0 r0 ; 5
+
+    mov 3 r2
+    neg r2
+    st 1 r2 ; -3
+
+    mov 7 r3
+    mov 9 r4
+    swap r3 r4
+    st 2 r3 ; 9
+    st 3 r4 ; 7
+
+    mov16 0x1234 r5 r6
+    st 4 r5 ; 0x34
+    st 5 r6 ; 0x12
+
+    jmp 1f
+    st 6 0xff ; skipped
+1:
+    jge r0 r3 .skip ; 5 >= 9 is false
+    st 6 1
+.skip:
+    halt
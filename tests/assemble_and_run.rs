@@ -1,8 +1,18 @@
 #![feature(decl_macro)]
 
+use leg_cpu_emulator::assembler::debug_info::DebugInfo;
 use leg_cpu_emulator::assembler::Assembler;
-use leg_cpu_emulator::emulator::Emulator;
+use leg_cpu_emulator::device::{Console, Device, Display, Keypad, Random, Timer};
+use leg_cpu_emulator::emulator::{Emulator, Fault, FaultKind, InputPolicy, StackKind, StopReason};
+use leg_cpu_emulator::header::{DataSegment, Header, HeaderError, HeaderVersion};
+use leg_cpu_emulator::instruction::Opcode;
+use leg_cpu_emulator::io::{Callback, RingBuffer, Timestamped};
+use leg_cpu_emulator::stack::OverflowBehavior;
+use leg_cpu_emulator::trace::{Trace, TraceWrite};
+use std::cell::{Cell, RefCell};
+use std::io;
 use std::io::BufRead;
+use std::rc::Rc;
 
 macro test_asm($name:literal) {
     include_str!(concat!("../tests/asm/", $name, ".asm"))
@@ -10,26 +20,21 @@ macro test_asm($name:literal) {
 
 fn emulator_run(bin: impl Into<Vec<u8>>) -> (Emulator, Vec<u8>) {
     let mut emulator = Emulator::new(bin).unwrap();
-    let mut output = Vec::new();
-    loop {
-        // println!("PC: {}", emulator.pc.usize());
-        emulator.tick().unwrap();
-        if emulator.halted {
-            break;
-        }
-        if let Some(x) = emulator.output {
-            output.push(*x);
-        }
-    }
+    let output = emulator.run_to_halt().unwrap();
     (emulator, output)
 }
 
 fn assemble_binary(code: &str) -> Vec<u8> {
-    Assembler::new(code).unwrap().assemble().binary.merge()
+    Assembler::new(code)
+        .unwrap()
+        .assemble()
+        .unwrap()
+        .binary
+        .merge()
 }
 
 fn assemble_and_run(code: &str) -> (Emulator, Vec<u8>) {
-    let target = Assembler::new(code).unwrap().assemble();
+    let target = Assembler::new(code).unwrap().assemble().unwrap();
     println!("{}", target.commented_binary);
     emulator_run(target.binary.merge())
 }
@@ -38,7 +43,7 @@ fn assemble_and_run(code: &str) -> (Emulator, Vec<u8>) {
 fn asm_hello_world() {
     let code = test_asm!("hello_world");
     let assembler = Assembler::new(code).unwrap();
-    let target = assembler.assemble();
+    let target = assembler.assemble().unwrap();
     println!("{}", target.commented_binary);
     let output = emulator_run(target.binary.merge()).1;
     assert_eq!(&output, b"hello, world\n");
@@ -47,7 +52,7 @@ fn asm_hello_world() {
 #[test]
 fn asm_fibonacci() {
     let code = test_asm!("fibonacci");
-    let target = Assembler::new(code).unwrap().assemble();
+    let target = Assembler::new(code).unwrap().assemble().unwrap();
     println!("{}", target.commented_binary);
     let emulator = emulator_run(target.binary.merge()).0;
     assert_eq!(&emulator.ram[..10], &[1, 1, 2, 3, 5, 8, 13, 21, 34, 55]);
@@ -59,17 +64,130 @@ fn asm_selection_sort() {
     assert_eq!(&ram[..16], &(0..16).collect::<Vec<u8>>())
 }
 
+#[test]
+fn local_labels() {
+    let target = Assembler::new(test_asm!("local_labels"))
+        .unwrap()
+        .assemble()
+        .unwrap();
+    assert!(target.labels.contains_key("f_sort.inner"));
+    assert!(target.labels.contains_key("f_reverse.loop"));
+    let ram = emulator_run(target.binary.merge()).0.ram;
+    assert_eq!(&ram[..16], &(0..16).rev().collect::<Vec<u8>>())
+}
+
+#[test]
+fn pseudo_instructions() {
+    let ram = assemble_and_run(test_asm!("pseudo_instructions")).0.ram;
+    assert_eq!(ram[..7], [5, (-3_i8) as u8, 9, 7, 0x34, 0x12, 1]);
+}
+
+#[test]
+fn data_segments() {
+    let target = Assembler::new(test_asm!("data_segments"))
+        .unwrap()
+        .assemble()
+        .unwrap();
+    let header = Header::parse(&target.binary.header).unwrap();
+    assert_eq!(header.version, HeaderVersion::V2);
+    let starts = header
+        .segments
+        .iter()
+        .map(|x| (x.mem_start, x.data.len()))
+        .collect::<Vec<_>>();
+    assert_eq!(starts, [(0x00, 5), (0x80, 18), (0xe0, 0x20)]);
+
+    let output = emulator_run(target.binary.merge()).1;
+    assert_eq!(output, b"squares: 1 4 9 16");
+}
+
 #[test]
 fn sixteen_bits_addressing() {
     let output = assemble_and_run(test_asm!("16bit_addressing")).1[0];
     assert_eq!(output, 5 /* 2 + 3 */);
 }
 
+#[test]
+fn header_versions() {
+    // the entrypoint is out of the 8-bit range
+    let code = format!(
+        ".data 0x10\nmsg 'ok' _\n.entry start\n.code\n{}start:\nld 0x10 out\nld 0x11 out\nhalt\n",
+        "nop\n".repeat(70)
+    );
+    let target = Assembler::new(&code).unwrap().assemble().unwrap();
+    let binary = target.binary.merge();
+    let header = Header::parse(&binary).unwrap();
+    // 4 + 3 + 2 bytes of header, then 70 `nop`s
+    assert_eq!(header.version, HeaderVersion::V2);
+    assert_eq!(header.entrypoint, 9 + 70 * 4);
+    assert_eq!(binary[..9], [2, 0x21, 0x01, 1, 0x10, 2, 0, b'o', b'k']);
+    assert_eq!(header.encode().unwrap(), binary[..9]);
+    assert_eq!(emulator_run(binary).1, b"ok");
+
+    // version 1 is kept where possible
+    let binary = assemble_binary(".data 0x10\nmsg 'ok' _\n.entry start\n.code\nstart:\nhalt\n");
+    assert_eq!(binary[..6], [1, 2, 0x10, 6, b'o', b'k']);
+
+    // several segments
+    let header = Header {
+        version: HeaderVersion::V2,
+        entrypoint: 4 + 6 + 3,
+        segments: vec![
+            DataSegment {
+                mem_start: 0x20,
+                data: vec![1, 2],
+            },
+            DataSegment {
+                mem_start: 0xff,
+                data: vec![3],
+            },
+        ],
+    };
+    let mut binary = header.encode().unwrap();
+    binary.extend([0x02, 0, 0, 0]);
+    assert_eq!(Header::parse(&binary).unwrap(), header);
+    let emulator = emulator_run(binary).0;
+    assert_eq!(emulator.ram[0x20..0x22], [1, 2]);
+    assert_eq!(emulator.ram[0xff], 3);
+
+    // a segment beyond the RAM, and a truncated one
+    let segment = |mem_start, len| [2, 10, 0, 1, mem_start, len, 0];
+    let header_error = |binary: Vec<u8>| {
+        Emulator::new(binary)
+            .unwrap_err()
+            .downcast::<HeaderError>()
+            .unwrap()
+    };
+    assert_eq!(
+        header_error([&segment(0xff, 2)[..], &[0; 6]].concat()),
+        HeaderError::SegmentPastRam {
+            mem_start: 0xff,
+            len: 2
+        }
+    );
+    assert_eq!(header_error(segment(0, 2).into()), HeaderError::Truncated);
+    // version 1, with 4 bytes of data at 0xfe
+    assert_eq!(
+        header_error(vec![1, 4, 0xfe, 8, 1, 2, 3, 4]),
+        HeaderError::SegmentPastRam {
+            mem_start: 0xfe,
+            len: 4
+        }
+    );
+    assert_eq!(header_error(vec![1, 4, 0, 8, 1]), HeaderError::Truncated);
+    assert_eq!(header_error(vec![1, 0]), HeaderError::TooShort);
+    assert_eq!(
+        header_error(vec![0x7f, 0, 0, 0]),
+        HeaderError::UnknownVersion(0x7f)
+    );
+}
+
 #[test]
 fn multibyte_integer_add() {
     let target = Assembler::new(test_asm!("multibyte-integer-adding"))
         .unwrap()
-        .assemble();
+        .assemble()
+        .unwrap();
     let status = emulator_run(target.binary.merge()).1[0];
     assert_eq!(status, 0);
 }
@@ -80,6 +198,34 @@ fn function_stack() {
     assert_eq!(&ram[0..5], &[6, 7, 8, 9, 10]);
 }
 
+#[test]
+fn macros() {
+    let ram = assemble_and_run(test_asm!("macros")).0.ram;
+    assert_eq!(&ram[0..5], &[6, 7, 8, 9, 10]);
+}
+
+#[test]
+fn include() {
+    let path = "tests/asm/include.asm";
+    let code = std::fs::read_to_string(path).unwrap();
+    let target = Assembler::with_file_name(code, path)
+        .unwrap()
+        .assemble()
+        .unwrap();
+    let output = emulator_run(target.binary.merge()).1;
+    assert_eq!(output, b"0\n7\n42\n100\n255\n");
+    let files = target
+        .debug_info
+        .lines
+        .iter()
+        .map(|x| x.file.as_str())
+        .collect::<std::collections::HashSet<_>>();
+    assert_eq!(
+        files,
+        ["tests/asm/include.asm", "tests/asm/lib/print.asm"].into()
+    );
+}
+
 #[test]
 fn input_output() {
     let input = vec![0, 1, 2];
@@ -104,7 +250,10 @@ fn water_world() {
         ("5,6,2,5,1,3,2,1,1,1,1,1,1,1,1,1", 5),
     ];
 
-    let target = Assembler::new(test_asm!("water_world")).unwrap().assemble();
+    let target = Assembler::new(test_asm!("water_world"))
+        .unwrap()
+        .assemble()
+        .unwrap();
     for (line, expected) in data {
         let mut emulator = Emulator::new(target.binary.merge()).unwrap();
         emulator.set_input(format!("{line}\n").as_bytes());
@@ -135,3 +284,479 @@ fn prime_numbers() {
     }
     assert_eq!(expected, list);
 }
+
+#[test]
+fn debug_info() {
+    let target = Assembler::with_file_name(test_asm!("hello_world"), "hello_world.asm")
+        .unwrap()
+        .assemble()
+        .unwrap();
+    let info = &target.debug_info;
+    assert_eq!(info.labels["for1"], 0x14);
+
+    let line = info.find(0x10).unwrap();
+    assert_eq!(line.file, "hello_world.asm");
+    assert_eq!(line.line, 8);
+    assert_eq!(line.label.as_deref(), Some("start"));
+    assert_eq!(line.statement, "cp 0 r0");
+    let line = info.find(0x18).unwrap();
+    assert_eq!((line.line, line.label.as_deref()), (11, Some("for1")));
+    assert!(info.find(0x11).is_none());
+
+    let parsed = info.to_string().parse::<DebugInfo>().unwrap();
+    assert_eq!(&parsed, info);
+}
+
+#[test]
+fn trace() {
+    let binary = assemble_binary(test_asm!("input_output"));
+    let mut emulator = Emulator::new(binary.clone()).unwrap();
+    emulator.set_input([0xff, 1, 2]).enable_trace();
+    emulator.run_to_halt().unwrap();
+    let trace = emulator.trace.take().unwrap();
+    // 9 instructions plus `halt`
+    assert_eq!(trace.entries.len(), 10);
+
+    let first = &trace.entries[0];
+    assert_eq!(first.opcode(), Some(Opcode::Copy));
+    assert_eq!(first.input, Some(0xff));
+    assert_eq!(first.write, Some(TraceWrite::Register(0, 0xff)));
+    // 0xff + 1 overflows
+    let add = &trace.entries[3];
+    assert_eq!(add.operands, [0xff, 1]);
+    assert_eq!(add.write, Some(TraceWrite::Register(0, 0)));
+    assert_eq!(add.carry, Some(true));
+    assert_eq!(trace.entries[4].carry, Some(false));
+    assert_eq!(trace.entries[6].output, Some(0));
+
+    let mut file = Vec::new();
+    trace.write_to(&mut file).unwrap();
+    let read = Trace::read_from(&file[..]).unwrap();
+    assert_eq!(read, trace);
+    assert_eq!(read.first_divergence(&trace), None);
+
+    // the same program with a different input diverges at the first tick
+    let mut emulator = Emulator::new(binary).unwrap();
+    emulator.set_input([0, 1, 2]).enable_trace();
+    emulator.run_to_halt().unwrap();
+    assert_eq!(emulator.trace.unwrap().first_divergence(&trace), Some(0));
+
+    let mut truncated = trace.clone();
+    truncated.entries.pop();
+    assert_eq!(truncated.first_divergence(&trace), Some(9));
+}
+
+#[test]
+fn cycle_budget() {
+    let code = ".entry start\n.code\nstart:\n    cp 1 out\n    jamv start\n    jpeq 0 0\n";
+    let mut emulator = Emulator::new(assemble_binary(code)).unwrap();
+    let reason = emulator.run(Some(10)).unwrap();
+    assert_eq!(reason, StopReason::BudgetExhausted);
+    assert_eq!(emulator.cycles, 10);
+    assert_eq!(emulator.take_output(), [1, 1, 1, 1]);
+
+    // resumes with a new budget
+    assert_eq!(emulator.run(Some(5)).unwrap(), StopReason::BudgetExhausted);
+    assert_eq!(emulator.cycles, 15);
+
+    let mut emulator = Emulator::new(assemble_binary(test_asm!("hello_world"))).unwrap();
+    let reason = emulator.run(Some(1000)).unwrap();
+    assert_eq!(reason, StopReason::Halted);
+    assert!(emulator.cycles < 1000);
+}
+
+#[test]
+fn output_sinks() {
+    let code = ".entry start\n.code\nstart:\n    cp 1 out\n    nop\n    cp 2 out\n    cp 3 out\n    halt\n";
+    let binary = assemble_binary(code);
+
+    // not lost when ticking by hand
+    let mut emulator = Emulator::new(binary.clone()).unwrap();
+    while !emulator.halted {
+        emulator.tick().unwrap();
+    }
+    assert_eq!(emulator.take_output(), [1, 2, 3]);
+    assert_eq!(emulator.take_output(), []);
+
+    let mut emulator = Emulator::new(binary.clone()).unwrap();
+    emulator.set_output(RingBuffer::new(2));
+    emulator.run(None).unwrap();
+    let buffer = emulator.output::<RingBuffer>().unwrap();
+    assert_eq!(
+        buffer.entries().iter().copied().collect::<Vec<_>>(),
+        [
+            Timestamped { cycle: 3, byte: 2 },
+            Timestamped { cycle: 4, byte: 3 }
+        ]
+    );
+    assert_eq!(buffer.dropped, 1);
+    assert!(emulator.output::<Vec<u8>>().is_none());
+    assert_eq!(emulator.take_output(), []);
+
+    let written = Rc::new(RefCell::new(Vec::new()));
+    let mut emulator = Emulator::new(binary).unwrap();
+    let sink = Rc::clone(&written);
+    emulator.set_output(Callback(move |byte, cycle| {
+        sink.borrow_mut().push((byte, cycle))
+    }));
+    emulator.run(None).unwrap();
+    assert_eq!(*written.borrow(), [(1, 1), (2, 3), (3, 4)]);
+
+    let binary = assemble_binary(test_asm!("input_output"));
+    let mut emulator = Emulator::new(binary).unwrap();
+    let mut next = 0;
+    emulator.set_input_source(Callback(move || {
+        next += 1;
+        (next <= 2).then_some(next)
+    }));
+    assert_eq!(emulator.run_to_halt().unwrap(), [2, 3, 1]);
+}
+
+#[test]
+fn input_policies() {
+    let binary = assemble_binary(test_asm!("input_output"));
+    let mut emulator = Emulator::new(binary.clone()).unwrap();
+    emulator
+        .set_input([0, 1])
+        .set_input_policy(InputPolicy::Block);
+    let reason = emulator.run(None).unwrap();
+    assert_eq!(reason, StopReason::InputExhausted);
+    // stuck at the third `cp in r2`
+    assert_eq!(*emulator.pc, 12);
+    assert_eq!(emulator.cycles, 2);
+
+    emulator.input.extend([2]);
+    assert_eq!(emulator.run(None).unwrap(), StopReason::Halted);
+    assert_eq!(emulator.take_output(), [1, 2, 3]);
+
+    let mut emulator = Emulator::new(binary.clone()).unwrap();
+    emulator.set_input([5]);
+    assert_eq!(emulator.run_to_halt().unwrap(), [6, 1, 1]);
+
+    let mut emulator = Emulator::new(binary).unwrap();
+    emulator.set_input([5]).set_input_policy(InputPolicy::Fault);
+    let reason = emulator.run(None).unwrap();
+    let StopReason::Fault(fault) = reason else {
+        panic!("{reason:?}");
+    };
+    assert_eq!((fault.pc, fault.kind), (8, FaultKind::InputExhausted));
+}
+
+#[test]
+fn streaming_input() {
+    /// Hands out one byte per read, and records how many were taken.
+    struct Reader(Rc<Cell<usize>>);
+
+    impl io::Read for Reader {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            let taken = self.0.get();
+            if taken == 3 {
+                return Ok(0);
+            }
+            self.0.set(taken + 1);
+            buf[0] = taken as u8 * 10;
+            Ok(1)
+        }
+    }
+
+    let binary = assemble_binary(test_asm!("input_output"));
+    let mut emulator = Emulator::new(binary).unwrap();
+    let taken = Rc::new(Cell::new(0));
+    emulator.set_input_reader(Reader(Rc::clone(&taken)));
+    // pulled only as `in` is read
+    emulator.tick().unwrap();
+    assert_eq!(taken.get(), 1);
+    emulator.tick().unwrap();
+    assert_eq!(taken.get(), 2);
+    assert_eq!(emulator.run_to_halt().unwrap(), [1, 11, 21]);
+    assert_eq!(taken.get(), 3);
+}
+
+#[test]
+fn strict_faults() {
+    fn run(binary: Vec<u8>, strict: bool) -> StopReason {
+        let mut emulator = Emulator::new(binary).unwrap();
+        emulator.set_strict(strict);
+        emulator.run(Some(1000)).unwrap()
+    }
+    let fault_kind = |binary: Vec<u8>| match run(binary, true) {
+        StopReason::Fault(x) => x.kind,
+        x => panic!("Unexpected stop: {x:?}"),
+    };
+    let program =
+        |body: &str| assemble_binary(&format!(".entry start\n.code\nstart:\n{body}\nhalt\n"));
+
+    let binary = program("cp 0 r1\ndiv 7 r1 r2\ncp r2 out");
+    let mut emulator = Emulator::new(binary.clone()).unwrap();
+    assert_eq!(emulator.run_to_halt().unwrap(), [0]);
+    let mut emulator = Emulator::new(binary).unwrap();
+    emulator.set_strict(true);
+    assert_eq!(
+        emulator.run(None).unwrap(),
+        StopReason::Fault(Fault {
+            pc: 8,
+            inst: [0x98, 7, 1, 2],
+            kind: FaultKind::DivisionByZero,
+        })
+    );
+    // stays at the faulting instruction
+    assert_eq!(*emulator.pc, 8);
+    assert!(emulator.run_to_halt().is_err());
+
+    assert_eq!(run(program("pop r0"), false), StopReason::Halted);
+    assert_eq!(
+        fault_kind(program("pop r0")),
+        FaultKind::StackUnderflow(StackKind::Data)
+    );
+    assert_eq!(
+        fault_kind(program("ret")),
+        FaultKind::StackUnderflow(StackKind::Call)
+    );
+    assert_eq!(
+        fault_kind(program("fpop r0")),
+        FaultKind::StackUnderflow(StackKind::Args)
+    );
+    assert_eq!(
+        fault_kind(program("loop:\npush 1\njamv loop\njp")),
+        FaultKind::StackOverflow(StackKind::Data)
+    );
+    // no `halt`; runs off the end
+    assert_eq!(
+        fault_kind(assemble_binary(".entry start\n.code\nstart:\nnop\n")),
+        FaultKind::PcOutOfBounds
+    );
+
+    // header (no data; entry point 4), then the instruction
+    let raw = |inst: [u8; 4]| [[1, 0, 0, 4], inst].concat();
+    assert_eq!(fault_kind(raw([0x3f, 0, 0, 0])), FaultKind::IllegalOpcode);
+    assert_eq!(
+        fault_kind(raw([0x83, 1, 0, 0x20])),
+        FaultKind::InvalidRegister(0x20)
+    );
+    assert_eq!(
+        fault_kind(raw([0x03, 0x20, 0, 0])),
+        FaultKind::InvalidRegister(0x20)
+    );
+}
+
+#[test]
+fn bounded_stacks() {
+    let code =
+        ".entry start\n.code\nstart:\npush 1\npush 2\npush 3\npop out\npop out\npop out\nhalt\n";
+    let binary = assemble_binary(code);
+    let run = |behavior| {
+        let mut emulator = Emulator::new(binary.clone()).unwrap();
+        emulator.set_stacks(2, behavior);
+        let reason = emulator.run(None).unwrap();
+        (reason, emulator.take_output(), emulator.stack.pointer())
+    };
+
+    assert_eq!(
+        run(OverflowBehavior::Wrap),
+        (StopReason::Halted, vec![3, 2, 3], 0)
+    );
+    assert_eq!(
+        run(OverflowBehavior::Saturate),
+        (StopReason::Halted, vec![2, 1, 0], 0)
+    );
+    let (reason, output, pointer) = run(OverflowBehavior::Fault);
+    assert!(matches!(
+        reason,
+        StopReason::Fault(Fault {
+            pc: 12,
+            kind: FaultKind::StackOverflow(StackKind::Data),
+            ..
+        })
+    ));
+    assert_eq!((output, pointer), (vec![], 2));
+}
+
+#[test]
+fn devices() {
+    let code = r#"
+.consts
+CONSOLE 0xf0
+TIMER 0xf2
+RANDOM 0xf4
+KEYPAD 0xf6
+
+.entry main
+.code
+main:
+    ld KEYPAD r0         ; the first key
+    st CONSOLE r0
+    ld KEYPAD+1 r1       ; keys left
+    st CONSOLE r1
+    ld CONSOLE r2        ; console input
+    st CONSOLE r2
+    ld TIMER r3          ; 6 cycles so far
+    st TIMER 0           ; reset
+    ld TIMER r4
+    st RANDOM 7          ; reseed
+    ld RANDOM r5
+    st 0xf8 r5           ; plain RAM
+    halt
+"#;
+    let target = Assembler::new(code).unwrap().assemble().unwrap();
+    let mut emulator = Emulator::new(target.binary.merge()).unwrap();
+    let mut keypad = Keypad::default();
+    keypad.press(b'a');
+    keypad.press(b'b');
+    emulator
+        .attach(0xf0, Console::default())
+        .unwrap()
+        .attach(0xf2, Timer::default())
+        .unwrap()
+        .attach(0xf4, Random::default())
+        .unwrap()
+        .attach(0xf6, keypad)
+        .unwrap();
+    emulator
+        .device_mut::<Console>()
+        .unwrap()
+        .input
+        .push_back(b'x');
+    emulator.run_to_halt().unwrap();
+
+    let registers = emulator.registers.tier1();
+    assert_eq!(registers[..5], [b'a', 1, b'x', 6, 1]);
+    let mut random = Random::new(7);
+    assert_eq!(registers[5], random.read(0));
+    assert_eq!(emulator.ram[0xf8], registers[5]);
+    // the device registers don't go to the RAM
+    assert_eq!(emulator.ram[0xf0..0xf8], [0; 8]);
+    assert_eq!(
+        emulator.device::<Console>().unwrap().output,
+        [b'a', 1, b'x']
+    );
+    // 6 instructions since the reset, including `halt`
+    assert_eq!(emulator.device::<Timer>().unwrap().cycles, 6);
+    assert_eq!(emulator.device::<Keypad>().unwrap().pressed, [b'b']);
+
+    assert_eq!(
+        emulator
+            .attach(0xf3, Timer::default())
+            .unwrap_err()
+            .to_string(),
+        "Device at 0xf3..0xf5 overlaps the one at 0xf2..0xf4"
+    );
+    assert_eq!(
+        emulator
+            .attach(0xff, Console::default())
+            .unwrap_err()
+            .to_string(),
+        "Device at 0xff of 2 bytes exceeds the RAM address space"
+    );
+}
+
+#[test]
+fn display() {
+    let target = Assembler::new(test_asm!("display"))
+        .unwrap()
+        .assemble()
+        .unwrap();
+    let mut emulator = Emulator::new(target.binary.merge()).unwrap();
+    let mut display = Display::new(8, 8);
+    display.snapshot_every = Some(4);
+    emulator.attach(0xf0, display).unwrap();
+    emulator.run_to_halt().unwrap();
+
+    let display = emulator.device::<Display>().unwrap();
+    assert_eq!(display.presented, 8);
+    let snapshots = display
+        .snapshots
+        .iter()
+        .map(|x| (x.number, x.to_ascii()))
+        .collect::<Vec<_>>();
+    assert_eq!(
+        snapshots,
+        [
+            (
+                4,
+                "1.......\n.2......\n..3.....\n...4....\n........\n........\n........\n........\n"
+                    .into()
+            ),
+            (
+                8,
+                "1.......\n.2......\n..3.....\n...4....\n....5...\n.....6..\n......7.\n.......8\n"
+                    .into()
+            ),
+        ]
+    );
+    let frame = display.frame();
+    assert_eq!(frame.number, 9);
+    assert_eq!(
+        frame.to_ascii(),
+        "1......f\n.2.....f\n..3....f\n...4...f\n....5..f\n.....6.f\n......7f\n.......f\n"
+    );
+
+    let ppm = frame.to_ppm(2);
+    let header = b"P6\n16 16\n255\n";
+    assert_eq!(ppm[..header.len()], *header);
+    assert_eq!(ppm.len(), header.len() + 16 * 16 * 3);
+    // the first two pixels of the first row, color 1 scaled up
+    assert_eq!(
+        ppm[header.len()..][..12],
+        [0, 0, 0xaa, 0, 0, 0xaa, 0, 0, 0, 0, 0, 0]
+    );
+    assert_eq!(frame.to_png(1)[..8], *b"\x89PNG\r\n\x1a\n");
+    // clamped to 16
+    assert!(frame.to_ppm(u32::MAX).starts_with(b"P6\n128 128\n"));
+
+    // handed out as they're taken, instead of kept
+    let taken = Rc::new(RefCell::new(Vec::new()));
+    let mut emulator = Emulator::new(target.binary.merge()).unwrap();
+    let mut display = Display::new(8, 8);
+    display.snapshot_every = Some(3);
+    let sink = Rc::clone(&taken);
+    display.on_snapshot = Some(Box::new(move |x| sink.borrow_mut().push(x.number)));
+    emulator.attach(0xf0, display).unwrap();
+    emulator.run_to_halt().unwrap();
+    assert_eq!(*taken.borrow(), [3, 6]);
+    assert!(emulator.device::<Display>().unwrap().snapshots.is_empty());
+}
+
+#[test]
+fn multiplication() {
+    let code = ".entry start\n.code\nstart:\n    mull 20 30 out\n    mulh 20 30 out\n    mulh 255 255 out\n    halt\n";
+    // 600 is 0x0258, and 255 * 255 is 0xfe01
+    assert_eq!(assemble_and_run(code).1, [0x58, 0x02, 0xfe]);
+}
+
+#[test]
+fn wide_shifts() {
+    let code = ".entry start\n.code\nstart:\n    shl 1 7 out\n    shl 1 8 out\n    shr 0x80 9 out\n    cp 200 r0\n    shr 0xff r0 out\n    halt\n";
+    // shifting out all the bits gives 0 rather than panicking
+    assert_eq!(assemble_and_run(code).1, [0x80, 0, 0, 0]);
+}
+
+#[test]
+fn destination_doesnt_read_input() {
+    // `out` and `in` share the register code, and a destination isn't fetched
+    let code = ".data 0x10\nv 9\n.entry start\n.code\nstart:\n    ld v out\n    push 3\n    pop out\n    cp in out\n    halt\n";
+    let mut emulator = Emulator::new(assemble_binary(code)).unwrap();
+    emulator.set_input([7, 8]);
+    assert_eq!(emulator.run_to_halt().unwrap(), [9, 3, 7]);
+    assert_eq!(emulator.input.queued(), &[8]);
+}
+
+#[test]
+fn register_setters() {
+    let code = ".entry start\n.code\nstart:\n    mvc out\n    cp r3 out\n    jp\n    cp 1 out\n    halt\nend:\n    cp 2 out\n    halt\n";
+    let target = Assembler::new(code).unwrap().assemble().unwrap();
+    let mut emulator = Emulator::new(target.binary.merge()).unwrap();
+    emulator.registers.set_carry(true);
+    emulator.registers.set_jump_address(target.labels["end"]);
+    emulator.registers.tier1_mut()[3] = 5;
+    assert_eq!(emulator.run_to_halt().unwrap(), [1, 5, 2]);
+}
+
+#[test]
+fn move_carry() {
+    let code = ".data 0x80\nv [5, 6]\n.entry start\n.code\nstart:\n    add 200 100 r0\n    mvc out\n    add 1 1 r0\n    mvc r1\n    cp r1 out\n    ld v+1 out\n    halt\n";
+    let binary = assemble_binary(code);
+    // the data length, not where the data ends
+    assert_eq!(binary[..3], [1, 2, 0x80]);
+    let (_, output) = emulator_run(binary);
+    assert_eq!(output, [1, 0, 6]);
+}
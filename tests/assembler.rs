@@ -0,0 +1,952 @@
+use leg_cpu_emulator::assembler::diagnostic::{DiagnosticKind, Diagnostics};
+use leg_cpu_emulator::assembler::symbol::SymbolKind;
+use leg_cpu_emulator::assembler::Assembler;
+use leg_cpu_emulator::header::Header;
+use std::collections::HashMap;
+use std::path::PathBuf;
+
+fn assemble_err(code: &str) -> Diagnostics {
+    match Assembler::with_file_name(code, "prog.asm") {
+        Ok(assembler) => assembler.assemble().unwrap_err(),
+        Err(e) => e,
+    }
+}
+
+#[test]
+fn all_errors_in_one_pass() {
+    let code = "\
+.consts
+MAX 0x1ff
+
+.entry start
+
+.code
+start:
+    cp MAX r0
+    ad r0 r1 r2
+    jamv nowhere
+    add r0 1 r0 r1
+    cp 1 r99
+    halt
+";
+    let diagnostics = assemble_err(code).0;
+    let summary = diagnostics
+        .iter()
+        .map(|x| {
+            let location = x.location.as_ref().unwrap();
+            (x.kind, location.line, location.column)
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(
+        summary,
+        [
+            (DiagnosticKind::InvalidLiteral, 2, 5),
+            (DiagnosticKind::UnknownOpcode, 9, 5),
+            (DiagnosticKind::UndefinedSymbol, 10, 10),
+            (DiagnosticKind::OperandCount, 11, 17),
+            (DiagnosticKind::InvalidOperand, 12, 10),
+        ]
+    );
+    assert!(diagnostics.iter().all(|x| x.file == "prog.asm"));
+}
+
+#[test]
+fn rendering() {
+    let code = ".entry start\n.code\nstart:\n\tad r0 r1 r2\n    halt\n";
+    let rendered = assemble_err(code).to_string();
+    assert_eq!(
+        rendered,
+        "\
+error: Unknown opcode: ad
+ --> prog.asm:4:2
+  |
+4 | \tad r0 r1 r2
+  | \t^^
+
+error: aborting due to 1 previous error"
+    );
+}
+
+#[test]
+fn section_errors() {
+    let diagnostics = assemble_err(".entry\n.code\nhalt\n.code\n").0;
+    let kinds = diagnostics.iter().map(|x| x.kind).collect::<Vec<_>>();
+    assert_eq!(
+        kinds,
+        [DiagnosticKind::DuplicateSection, DiagnosticKind::Syntax]
+    );
+
+    let diagnostics = assemble_err(".entry start\n").0;
+    assert_eq!(diagnostics[0].kind, DiagnosticKind::MissingSection);
+    assert!(diagnostics[0].location.is_none());
+}
+
+#[test]
+fn macro_expansion() {
+    let code = "\
+.macro skip_if_zero x
+    jamv done
+    jpeq \\x 0
+    add \\x 1 \\x
+    done:
+.endm
+
+.entry start
+.code
+start:
+    skip_if_zero r0
+    skip_if_zero r1
+    address_of r2
+    halt
+
+.macro address_of r
+here:
+    cp lo(here) \\r
+    cp here+1 \\r
+    jamv here+0
+.endm
+";
+    let target = Assembler::new(code).unwrap().assemble().unwrap();
+    // each expansion gets its own `done`
+    assert_eq!(target.labels["skip_if_zero.0.done"], 16);
+    assert_eq!(target.labels["skip_if_zero.1.done"], 28);
+    assert!(target
+        .commented_binary
+        .contains("# add r1 1 r1 <- skip_if_zero r1 (<source>:12)\n"));
+    // also renamed in expressions
+    assert_eq!(target.labels["address_of.2.here"], 28);
+    let code = &target.binary.code[24..36];
+    assert_eq!(code[..4], [0x83, 28, 0, 2]);
+    assert_eq!(code[4..8], [0x83, 29, 0, 2]);
+    assert_eq!(code[8..12], [0x44, 0, 28, 0]);
+}
+
+#[test]
+fn macro_errors() {
+    let code = "\
+.macro inc x
+    ad \\x 1 \\x
+.endm
+.macro wrap x
+    inc \\x
+    nop
+.endm
+.macro forever
+    forever
+.endm
+
+.entry start
+.code
+start:
+    inc r0 r1
+    forever
+    wrap r0
+    halt
+";
+    let rendered = assemble_err(code).to_string();
+    assert_eq!(
+        rendered,
+        "\
+error: Macro `inc` takes 1 argument(s) but 2 given
+  --> prog.asm:15:12
+   |
+15 |     inc r0 r1
+   |            ^^
+
+error: Recursion limit (64) reached while expanding macro `forever`
+  --> prog.asm:16:5
+   |
+16 |     forever
+   |     ^^^^^^^
+
+error: Unknown opcode: ad
+ --> prog.asm:2:5
+  |
+2 |     ad r0 1 r0
+  |     ^^
+note: in this expansion of macro `inc`
+ --> prog.asm:5:5
+  |
+5 |     inc r0
+  |     ^^^
+note: in this expansion of macro `wrap`
+  --> prog.asm:17:5
+   |
+17 |     wrap r0
+   |     ^^^^
+
+error: aborting due to 3 previous errors"
+    );
+}
+
+#[test]
+fn macro_definition_errors() {
+    let code = "\
+.macro add x
+.endm
+.macro m x x
+.endm
+.macro m2 x
+    cp \\y r0
+.endm
+.macro m2
+.endm
+.entry start
+.code
+start:
+    halt
+.macro unterminated
+";
+    let diagnostics = assemble_err(code).0;
+    let summary = diagnostics
+        .iter()
+        .map(|x| (x.kind, x.location.as_ref().unwrap().line))
+        .collect::<Vec<_>>();
+    assert_eq!(
+        summary,
+        [
+            (DiagnosticKind::Syntax, 14),
+            (DiagnosticKind::Syntax, 1),
+            (DiagnosticKind::Syntax, 3),
+            (DiagnosticKind::UndefinedSymbol, 6),
+            (DiagnosticKind::DuplicateMacro, 8),
+        ]
+    );
+}
+
+fn assemble_files_err(files: &[(&str, &str)]) -> Diagnostics {
+    let loader = files
+        .iter()
+        .map(|&(path, code)| (PathBuf::from(path), code.to_string()))
+        .collect::<HashMap<_, _>>();
+    match Assembler::with_loader(files[0].1, files[0].0, &loader) {
+        Ok(assembler) => assembler.assemble().unwrap_err(),
+        Err(e) => e,
+    }
+}
+
+#[test]
+fn include_errors() {
+    let diagnostics = assemble_files_err(&[
+        (
+            "src/main.asm",
+            ".include lib/a.asm\n.include missing.asm\n.entry start\n.code\nstart:\n    call f\n",
+        ),
+        (
+            "src/lib/a.asm",
+            ".include ../lib/b.asm\n.entry f\n.code\nf:\n    ad r0 r1 r2\n",
+        ),
+        ("src/lib/b.asm", ".include ../main.asm\n.consts\nX 0x100\n"),
+    ])
+    .0;
+    let summary = diagnostics
+        .iter()
+        .map(|x| {
+            let location = x.location.as_ref().unwrap();
+            (x.kind, x.file.as_str(), location.line)
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(
+        summary,
+        [
+            (DiagnosticKind::IncludeCycle, "src/lib/b.asm", 1),
+            (DiagnosticKind::DuplicateSection, "src/lib/a.asm", 2),
+            (DiagnosticKind::IncludeFailed, "src/main.asm", 2),
+            (DiagnosticKind::InvalidLiteral, "src/lib/b.asm", 3),
+            (DiagnosticKind::UnknownOpcode, "src/lib/a.asm", 5),
+        ]
+    );
+    assert_eq!(
+        diagnostics[0].message,
+        "Include cycle: src/main.asm -> src/lib/a.asm -> src/lib/b.asm -> src/main.asm"
+    );
+}
+
+#[test]
+fn expressions() {
+    let code = "\
+.consts
+WIDTH 8
+HEIGHT WIDTH / 2
+SIZE (WIDTH * HEIGHT)
+MASK ~0x0f & 0xff
+
+.data SIZE - 2
+text 'hi' text_len
+
+.entry start
+.code
+start:
+    cp text+1 r0
+    cp (text_len - 1) r1
+    cp 'A' r2
+    cp '\\n' r3
+    cp -1 r4
+    cp lo(end) r5
+    cp hi(end) r6
+    cp MASK|1 r7
+    cp 1<<7 r8
+    jamv (end + 0)
+    halt
+end:
+";
+    let target = Assembler::new(code).unwrap().assemble().unwrap();
+    let binary = target.binary.merge();
+    // header and data
+    assert_eq!(binary[..6], [1, 2, 30, 6, b'h', b'i']);
+    let immediates = binary[6..]
+        .chunks(4)
+        .take(9)
+        .map(|x| x[1])
+        .collect::<Vec<_>>();
+    assert_eq!(immediates, [31, 1, b'A', b'\n', 0xff, 50, 0, 0xf1, 0x80]);
+    assert_eq!(binary[6 + 9 * 4 + 2..][..2], [50, 0]);
+}
+
+#[test]
+fn expression_errors() {
+    let code = "\
+.consts
+A 0x100 - 1
+B A + 1
+C UNKNOWN * 2
+D 1 / (A - A)
+E (1 + 2
+F 'ab'
+
+.entry start
+.code
+start:
+    cp hi(0x10000) r0
+    cp A+ r0
+    jamv (start + 0x10000)
+";
+    let diagnostics = assemble_err(code).0;
+    let summary = diagnostics
+        .iter()
+        .map(|x| {
+            let location = x.location.as_ref().unwrap();
+            (x.kind, location.line, location.column, x.message.as_str())
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(
+        summary,
+        [
+            (
+                DiagnosticKind::Overflow,
+                3,
+                3,
+                "`A + 1` is 256, which doesn't fit in a byte"
+            ),
+            (
+                DiagnosticKind::UndefinedSymbol,
+                4,
+                3,
+                "Undefined constant: UNKNOWN"
+            ),
+            (DiagnosticKind::DivisionByZero, 5, 3, "Division by zero"),
+            (DiagnosticKind::Syntax, 6, 9, "Expected `)`: unexpected end"),
+            (
+                DiagnosticKind::Syntax,
+                7,
+                3,
+                "Invalid character literal: `'ab'`"
+            ),
+            (
+                DiagnosticKind::Overflow,
+                12,
+                11,
+                "`0x10000` is 65536, which doesn't fit in 16 bits"
+            ),
+            (
+                DiagnosticKind::Syntax,
+                13,
+                10,
+                "Expected a value: unexpected end"
+            ),
+            (
+                DiagnosticKind::Overflow,
+                14,
+                10,
+                "`(start + 0x10000)` is 65540, which doesn't fit in 16 bits"
+            ),
+        ]
+    );
+}
+
+#[test]
+fn symbols() {
+    let code = "\
+.consts
+N 2
+
+.data 0x10
+arr [1, 2] len
+
+.entry start
+.code
+start:
+    ld arr r0
+    cp len r1
+    jamv end
+    jpeq r0 N
+    cp lo(end) r2
+end:
+    halt
+";
+    let target = Assembler::new(code).unwrap().assemble().unwrap();
+    let symbols = &target.symbols;
+    let symbol = |name| {
+        let x = symbols.get(name).unwrap();
+        (x.kind, x.value)
+    };
+    assert_eq!(symbol("N"), (SymbolKind::Const, 2));
+    assert_eq!(symbol("arr"), (SymbolKind::Data, 0x10));
+    assert_eq!(symbol("len"), (SymbolKind::Const, 2));
+    assert_eq!(symbol("end"), (SymbolKind::Label, 6 + 5 * 4));
+    assert_eq!(target.labels.len(), 2);
+    // `ld arr r0`
+    assert_eq!(target.binary.code[..4][1], 0x10);
+}
+
+#[test]
+fn symbol_errors() {
+    let code = format!(
+        "\
+.consts
+N 4
+
+.data 0
+arr [1, 2] N
+
+.entry start
+.code
+start:
+    call arr
+    jamv (arr + 4)
+    ld far r0
+    cp ((far - start) >> 2) r0
+{}far:
+    halt
+start:
+",
+        "    add r0 1 r0\n".repeat(64)
+    );
+    let diagnostics = assemble_err(&code).0;
+    let summary = diagnostics
+        .iter()
+        .map(|x| {
+            let location = x.location.as_ref().unwrap();
+            (x.kind, location.line, location.column, x.message.as_str())
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(
+        summary,
+        [
+            (
+                DiagnosticKind::DuplicateSymbol,
+                5,
+                12,
+                "`N` is already defined as a constant"
+            ),
+            (
+                DiagnosticKind::DuplicateSymbol,
+                80,
+                1,
+                "`start` is already defined as a label"
+            ),
+            (
+                DiagnosticKind::WrongSymbolKind,
+                10,
+                10,
+                "`arr` is a data symbol, not a code address"
+            ),
+            (
+                DiagnosticKind::WrongSymbolKind,
+                11,
+                11,
+                "`arr` is a data symbol, not a code address"
+            ),
+            (
+                DiagnosticKind::WrongSymbolKind,
+                12,
+                8,
+                "Label `far` is at 0x0116, which doesn't fit in a byte; use lo() or hi()"
+            ),
+        ]
+    );
+}
+
+#[test]
+fn register_names() {
+    let code = "\
+.data 0
+r0 [1]
+
+.entry start
+.code
+start:
+    cp 120 r0
+    cp r0 out
+    halt
+out:
+    halt
+";
+    let summary = assemble_err(code)
+        .0
+        .iter()
+        .map(|x| (x.kind, x.location.as_ref().unwrap().line, x.message.clone()))
+        .collect::<Vec<_>>();
+    assert_eq!(
+        summary,
+        [
+            (
+                DiagnosticKind::DuplicateSymbol,
+                2,
+                "`r0` is already defined as a register".into()
+            ),
+            (
+                DiagnosticKind::DuplicateSymbol,
+                10,
+                "`out` is already defined as a register".into()
+            ),
+        ]
+    );
+
+    // a constant still shadows it
+    let code = ".consts\nr1 7\n.entry start\n.code\nstart:\n    cp r1 out\n    halt\n";
+    let target = Assembler::new(code).unwrap().assemble().unwrap();
+    assert_eq!(target.binary.code[..4], [0x80 | 0x03, 7, 0, 12]);
+}
+
+#[test]
+fn local_label_errors() {
+    let code = "\
+.entry main
+.code
+.orphan:
+main:
+    jamv .missing
+    jp
+    jamv 2f
+    jp
+.loop:
+.loop:
+    cp 1b r0
+    jamv other.loop
+    jp
+other:
+.loop:
+    halt
+";
+    let diagnostics = assemble_err(code).0;
+    let summary = diagnostics
+        .iter()
+        .map(|x| {
+            let location = x.location.as_ref().unwrap();
+            (x.kind, location.line, location.column, x.message.as_str())
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(
+        summary,
+        [
+            (
+                DiagnosticKind::Syntax,
+                3,
+                1,
+                "Local label `.orphan` has no global label before it"
+            ),
+            (
+                DiagnosticKind::DuplicateSymbol,
+                10,
+                1,
+                "`main.loop` is already defined as a label"
+            ),
+            (
+                DiagnosticKind::UndefinedSymbol,
+                5,
+                10,
+                "Label not found: .missing"
+            ),
+            (
+                DiagnosticKind::UndefinedSymbol,
+                7,
+                10,
+                "No anonymous label `2` after this instruction"
+            ),
+            (
+                DiagnosticKind::UndefinedSymbol,
+                11,
+                8,
+                "No anonymous label `1` before this instruction"
+            ),
+        ]
+    );
+}
+
+#[test]
+fn pseudo_instruction_expansion() {
+    let code = "\
+.entry start
+.code
+start:
+    jmp end
+    inc r0
+    INC r1
+end:
+    halt
+";
+    let target = Assembler::new(code).unwrap().assemble().unwrap();
+    // two instructions for `jmp`
+    assert_eq!(target.labels["end"], 4 + 4 * 4);
+    assert!(target
+        .commented_binary
+        .contains("# jamv end <- jmp end (<source>:4)\n"));
+    assert!(target
+        .commented_binary
+        .contains("# jp <- jmp end (<source>:4)\n"));
+    assert!(target
+        .commented_binary
+        .contains("# add r0 1 r0 <- inc r0 (<source>:5)\n"));
+    assert!(target
+        .commented_binary
+        .contains("# add r1 1 r1 <- INC r1 (<source>:6)\n"));
+}
+
+#[test]
+fn pseudo_instruction_errors() {
+    let code = "\
+.entry start
+.code
+start:
+    inc r0 r1
+    jeq r0 1 nowhere
+    halt
+";
+    let diagnostics = assemble_err(code);
+    let kinds = diagnostics.0.iter().map(|x| x.kind).collect::<Vec<_>>();
+    assert_eq!(
+        kinds,
+        [
+            DiagnosticKind::OperandCount,
+            DiagnosticKind::UndefinedSymbol
+        ]
+    );
+    assert_eq!(
+        diagnostics.0[1].to_string(),
+        "\
+error: Label not found: nowhere
+ --> prog.asm:5:6
+  |
+5 | jamv nowhere
+  |      ^^^^^^^
+note: in this expansion of pseudo-instruction `jeq`
+ --> prog.asm:5:5
+  |
+5 |     jeq r0 1 nowhere
+  |     ^^^
+"
+    );
+}
+
+#[test]
+fn data_values() {
+    let code = r#"
+.consts
+N 3
+
+.data 0x10
+s 'a\n\x41''' s_len
+d "\"\0"
+z asciz 'hi' z_len
+b 0x7f
+arr [N, N * 2, 'c', lo(main), -1]
+w word 0x1234
+ws word [main, arr] ws_len
+t times N [1, 2] t_len
+r reserve 2
+align 4
+after 9
+
+.entry main
+.code
+main:
+    ld z r0
+    halt
+"#;
+    let target = Assembler::new(code).unwrap().assemble().unwrap();
+    let symbol = |name| target.symbols.get(name).unwrap().value;
+    let data = &target.binary.header[4..];
+    let main = target.labels["main"];
+    assert_eq!(main as usize, 4 + data.len());
+    #[rustfmt::skip]
+    let expected = [
+        b'a', b'\n', b'A', b'\'',
+        b'"', 0,
+        b'h', b'i', 0,
+        0x7f,
+        3, 6, b'c', main as u8, 0xff,
+        0x34, 0x12,
+        main as u8, 0, 0x10 + 10, 0,
+        1, 2, 1, 2, 1, 2,
+        0, 0,
+        0, 0, 0, // align
+        9,
+    ];
+    assert_eq!(data, expected);
+    assert_eq!(symbol("s"), 0x10);
+    assert_eq!(symbol("s_len"), 4);
+    assert_eq!(symbol("z_len"), 3);
+    assert_eq!(symbol("arr"), 0x10 + 10);
+    assert_eq!(symbol("ws_len"), 4);
+    assert_eq!(symbol("t_len"), 6);
+    assert_eq!(symbol("after"), 0x30);
+}
+
+#[test]
+fn quoted_semicolons() {
+    let code = r#"
+.data 0x10
+s 'a;b' s_len ; a comment
+d "\";'" ; 'not a string
+e 'it''s;' ; with a ';'
+
+.macro put_semicolon dst
+    cp ';' \dst ; \dst isn't used here
+.endm
+
+.entry main
+.code
+main:
+    cp ';' out ; prints ';'
+    cp '\'' out ; a quote
+    put_semicolon r1
+    halt
+"#;
+    let target = Assembler::new(code).unwrap().assemble().unwrap();
+    assert_eq!(&target.binary.header[4..], b"a;b\";'it's;");
+    assert_eq!(target.symbols.get("s_len").unwrap().value, 3);
+    let immediates = target
+        .binary
+        .code
+        .chunks(4)
+        .map(|x| x[1])
+        .collect::<Vec<_>>();
+    assert_eq!(immediates[..3], [b';', b'\'', b';']);
+    // the parameter after the quoted `;` is substituted
+    assert_eq!(target.binary.code[8..12], [0x83, b';', 0, 1]);
+}
+
+#[test]
+fn data_errors() {
+    let code = r#"
+.data 0xf0
+a 'abc\q'
+b [1, , 2]
+c [1, 2
+d 'abc
+e times 300 0
+f 1 len extra
+g [far]
+h word .local
+i reserve 0x0d ; up to 0xff
+j 1
+k
+
+.entry main
+.code
+main:
+    halt
+"#;
+    let diagnostics = assemble_err(code).0;
+    let summary = diagnostics
+        .iter()
+        .map(|x| {
+            let location = x.location.as_ref().unwrap();
+            (x.kind, location.line, location.column, x.message.as_str())
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(
+        summary,
+        [
+            (
+                DiagnosticKind::InvalidLiteral,
+                3,
+                7,
+                ".data: unknown escape: \\q"
+            ),
+            (DiagnosticKind::Syntax, 4, 3, ".data: empty array item"),
+            (DiagnosticKind::Syntax, 5, 3, ".data: expected `]`"),
+            (DiagnosticKind::Syntax, 6, 3, ".data: unterminated string"),
+            (
+                DiagnosticKind::Overflow,
+                7,
+                9,
+                "`300` is 300, which is not a count of 0 to 256"
+            ),
+            (
+                DiagnosticKind::Syntax,
+                8,
+                5,
+                ".data: unexpected text after the value"
+            ),
+            (
+                DiagnosticKind::Overflow,
+                12,
+                3,
+                ".data: `j` ends at 0x101, beyond the 8-bit address space"
+            ),
+            (
+                DiagnosticKind::Syntax,
+                13,
+                1,
+                ".data: expected `<name> <value> [<length-name>]`"
+            ),
+            (
+                DiagnosticKind::UndefinedSymbol,
+                9,
+                4,
+                "Undefined symbol: far"
+            ),
+            (
+                DiagnosticKind::UndefinedSymbol,
+                10,
+                8,
+                "Undefined symbol: .local"
+            ),
+        ]
+    );
+}
+
+#[test]
+fn data_segments() {
+    let files = [
+        (
+            "main.asm",
+            "\
+.include lib.asm
+.data 0
+a [1, 2]
+.data 0x10 pool
+b [3]
+.data 0x01 overlapping
+c 4
+.data 0x20 pool
+.entry main
+.code
+main:
+    halt
+",
+        ),
+        ("lib.asm", ".data 0x30\nd [5]\n.data 0x40 pool\ne [6]\n"),
+    ];
+    let diagnostics = assemble_files_err(&files).0;
+    let summary = diagnostics
+        .iter()
+        .map(|x| {
+            (
+                x.kind,
+                x.location.as_ref().unwrap().line,
+                x.message.as_str(),
+            )
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(
+        summary,
+        [
+            (
+                DiagnosticKind::DuplicateSection,
+                8,
+                "Duplicated section not allowed: .data"
+            ),
+            (
+                DiagnosticKind::DuplicateSection,
+                1,
+                "`.data 0x30` is merged into the segment at 0x00 of the including file; \
+                 give it another segment name"
+            ),
+            (
+                DiagnosticKind::DuplicateSection,
+                3,
+                "`.data 0x40 pool` is merged into the segment at 0x10 of the including file; \
+                 give it another segment name"
+            ),
+            (
+                DiagnosticKind::Overlap,
+                6,
+                "Data at 0x01..0x02 overlaps `.data 0` at 0x00..0x03"
+            ),
+        ]
+    );
+    assert_eq!(diagnostics[1].file, "lib.asm");
+
+    // without the errors, the included segments are merged
+    let code = files[0]
+        .1
+        .replace(".data 0x01 overlapping\nc 4\n.data 0x20 pool\n", "");
+    let mut loader = HashMap::new();
+    loader.insert(
+        PathBuf::from("lib.asm"),
+        ".data 0\nd [5]\n.data 0x10 pool\ne [6]\n".to_string(),
+    );
+    let target = Assembler::with_loader(code, "main.asm", &loader)
+        .unwrap()
+        .assemble()
+        .unwrap();
+    let header = Header::parse(&target.binary.header).unwrap();
+    let segments = header
+        .segments
+        .iter()
+        .map(|x| (x.mem_start, &x.data[..]))
+        .collect::<Vec<_>>();
+    assert_eq!(segments, [(0, &[1, 2, 5][..]), (0x10, &[3, 6][..])]);
+}
+
+#[test]
+fn listing() {
+    let code = r#"
+.data 0x10
+msg 'hi'
+buf reserve 5 buf_len
+
+.consts
+N 3
+
+.entry main
+.code
+main:
+    cp 'h' out ; print
+    inc r0
+    halt
+"#;
+    let target = Assembler::new(code).unwrap().assemble().unwrap();
+    let lines = target.listing.lines().collect::<Vec<_>>();
+    assert_eq!(
+        lines[..9],
+        [
+            "ADDR  BYTES        CYC  SOURCE",
+            "0000  01 07 10 0b       ; header v1",
+            "0004  68 69 00 00       ; data at 0x10",
+            "0008  00 00 00",
+            "000b                    <source>:11  main:",
+            "000b  83 68 00 0c  1    <source>:12  cp 'h' out ; print",
+            "000f  48 00 01 00  1    <source>:13  add r0 1 r0 <- inc r0 (<source>:13)",
+            "0013  02 00 00 00  1    <source>:14  halt",
+            "",
+        ]
+    );
+    assert_eq!(
+        lines[9..15],
+        [
+            "SYMBOL                          KIND      VALUE   SIZE",
+            "main                            label     0x000b",
+            "msg                             data      0x10    2",
+            "buf                             data      0x12    5",
+            "N                               const     3",
+            "buf_len                         const     5",
+        ]
+    );
+    assert_eq!(lines[16], "RAM   0 1 2 3 4 5 6 7 8 9 a b c d e f");
+    assert_eq!(lines[18], "10    # # # # # # # . . . . . . . . .");
+    assert_eq!(lines.last(), Some(&"7 of 256 bytes used"));
+}
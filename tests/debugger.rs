@@ -0,0 +1,60 @@
+#![feature(decl_macro)]
+
+use leg_cpu_emulator::assembler::Assembler;
+use leg_cpu_emulator::debugger::{Command, Debugger, Stop};
+use leg_cpu_emulator::emulator::Emulator;
+
+macro test_asm($name:literal) {
+    include_str!(concat!("../tests/asm/", $name, ".asm"))
+}
+
+fn debugger(code: &str) -> Debugger {
+    let target = Assembler::new(code).unwrap().assemble().unwrap();
+    let emulator = Emulator::new(target.binary.merge()).unwrap();
+    Debugger::new(emulator, target.debug_info)
+}
+
+#[test]
+fn breakpoint_on_label() {
+    let mut debugger = debugger(test_asm!("hello_world"));
+    let for1 = debugger.add_breakpoint("for1").unwrap();
+
+    // the first hit is before the first loop iteration
+    assert_eq!(
+        debugger.continue_execution().unwrap(),
+        Stop::Breakpoint(for1)
+    );
+    assert_eq!(debugger.emulator.registers.tier1()[0], 0);
+    assert!(debugger.take_output().is_empty());
+
+    assert_eq!(
+        debugger.continue_execution().unwrap(),
+        Stop::Breakpoint(for1)
+    );
+    assert_eq!(debugger.emulator.registers.tier1()[0], 1);
+    assert_eq!(debugger.take_output(), b"h");
+
+    debugger.remove_breakpoint("for1").unwrap();
+    assert_eq!(debugger.continue_execution().unwrap(), Stop::Halted);
+    assert_eq!(debugger.take_output(), b"ello, world\n");
+}
+
+#[test]
+fn step_and_commands() {
+    let mut debugger = debugger(test_asm!("hello_world"));
+    let start = debugger.resolve("start").unwrap();
+    assert_eq!(*debugger.emulator.pc, start);
+
+    assert_eq!(debugger.step(2).unwrap(), Stop::Stepped);
+    assert_eq!(*debugger.emulator.pc, start + 8);
+    assert_eq!(debugger.describe_address(start + 8), "0x0018 <for1+4>");
+
+    let command = "b 0x20".parse::<Command>().unwrap();
+    assert_eq!(command, Command::Break("0x20".into()));
+    debugger.execute(&command).unwrap();
+    let state = debugger.execute(&Command::Continue).unwrap();
+    assert!(state.starts_with("Breakpoint hit at 0x0020 <for1+12>\npc: 0x0020"));
+
+    assert!("step x".parse::<Command>().is_err());
+    assert!(debugger.add_breakpoint("no_such_label").is_err());
+}
@@ -0,0 +1,99 @@
+use leg_cpu_emulator::assembler::Assembler;
+use leg_cpu_emulator::disassembler::disassemble;
+
+fn assemble(code: &str) -> Vec<u8> {
+    Assembler::new(code)
+        .unwrap()
+        .assemble()
+        .unwrap()
+        .binary
+        .merge()
+}
+
+#[test]
+fn round_trip() {
+    let sources: [&str; 13] = [
+        include_str!("asm/16bit_addressing.asm"),
+        include_str!("asm/fibonacci.asm"),
+        include_str!("asm/function_stack.asm"),
+        include_str!("asm/hello_world.asm"),
+        include_str!("asm/input_output.asm"),
+        include_str!("asm/multibyte-integer-adding.asm"),
+        include_str!("asm/prime_numbers.asm"),
+        include_str!("asm/selection_sort.asm"),
+        include_str!("asm/water_world.asm"),
+        include_str!("asm/data_segments.asm"),
+        // data placed at a non-zero address, and all the operand kinds
+        ".data 0x10\ns 'ab' _\n.entry start\n.code\nstart:\nmvc r3\nld in out\nst 3 r1\npop fss\nnot r1 2 r11\nhalt\nend:\n",
+        ".data 0x20\n.entry start\n.code\nstart:\ncall start\n",
+        // version 2 header
+        &format!(".entry start\n.code\n{}start:\nhalt\n", "nop\n".repeat(70)),
+    ];
+    for source in sources {
+        let binary = assemble(source);
+        let disassembly = disassemble(&binary).unwrap();
+        println!("{}", disassembly.code);
+        assert!(disassembly.inexact.is_empty());
+        assert_eq!(assemble(&disassembly.code), binary);
+    }
+}
+
+#[test]
+fn output() {
+    let binary = assemble(include_str!("asm/hello_world.asm"));
+    let code = disassemble(&binary).unwrap().code;
+    let expected = "\
+.data 0x00
+data [0x68, 0x65, 0x6c, 0x6c, 0x6f, 0x2c, 0x20, 0x77, 0x6f, 0x72, 0x6c, 0x64] _
+
+.entry l_0010
+
+.code
+l_0010:
+    cp 0 r0                  ; 0x0010: 83000000
+l_0014:
+    add r0 0 r1              ; 0x0014: 48000001
+    ld r1 r1                 ; 0x0018: 28010100
+    cp r1 out                ; 0x001c: 0301000c
+    add r0 1 r0              ; 0x0020: 48000100
+    jamv l_0014              ; 0x0024: 44001400
+    jplt r0 12               ; 0x0028: 62000c00
+    cp 10 out                ; 0x002c: 830a000c
+    halt                     ; 0x0030: 02000000
+";
+    assert_eq!(code, expected);
+}
+
+#[test]
+fn numeric_targets() {
+    // unaligned, in the header and past the code
+    let source = ".entry start\n.code\nstart:\njamv 0x0009\ncall 2\njamv 0x1234\n";
+    let binary = assemble(source);
+    let disassembly = disassemble(&binary).unwrap();
+    assert!(disassembly.inexact.is_empty());
+    assert!(disassembly
+        .code
+        .contains("    jamv 0x0009              ; 0x0004: 44000900"));
+    assert_eq!(assemble(&disassembly.code), binary);
+}
+
+#[test]
+fn inexact() {
+    let mut binary = assemble(".entry start\n.code\nstart:\nhalt\n");
+    binary.push(0xff);
+    let disassembly = disassemble(&binary).unwrap();
+    assert_eq!(disassembly.inexact, [0x08]);
+    assert!(disassembly.code.contains("; trailing bytes dropped: ff"));
+    assert_eq!(assemble(&disassembly.code), binary[..binary.len() - 1]);
+
+    // unknown opcode, and `halt` with garbage operands
+    for inst in [[0x3f, 0, 0, 0], [0x02, 1, 0, 0]] {
+        let mut binary = assemble(".entry start\n.code\nstart:\nhalt\n");
+        binary.extend(inst);
+        let error = disassemble(&binary).unwrap_err().to_string();
+        assert!(error.starts_with("Instruction at 0x0008 has no assembly form"));
+    }
+
+    assert!(disassemble(&[1, 0, 0, 6, 0, 0, 0, 0]).is_err());
+    assert!(disassemble(&[0, 0, 0, 4]).is_err());
+}
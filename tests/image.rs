@@ -0,0 +1,104 @@
+use leg_cpu_emulator::assembler::{Assembler, BinaryParts};
+use leg_cpu_emulator::image::{self, ImageFormat};
+
+fn assemble(code: &str) -> BinaryParts {
+    Assembler::new(code).unwrap().assemble().unwrap().binary
+}
+
+#[test]
+fn round_trip() {
+    let formats = [
+        ImageFormat::IntelHex,
+        ImageFormat::Logisim,
+        ImageFormat::HexLines,
+        ImageFormat::Verilog,
+        ImageFormat::TuringComplete,
+    ];
+    let sources = [
+        include_str!("asm/hello_world.asm"),
+        include_str!("asm/data_segments.asm"),
+        // longer than a 16-byte line, and a version 2 header
+        &format!(".entry start\n.code\n{}start:\nhalt\n", "nop\n".repeat(70)),
+    ];
+    for source in sources {
+        let binary = assemble(source);
+        for format in formats {
+            let image = format.write(&binary);
+            assert_eq!(image::load(image.as_bytes()).unwrap(), binary.merge());
+        }
+        assert_eq!(image::load(&binary.merge()).unwrap(), binary.merge());
+    }
+}
+
+#[test]
+fn formats() {
+    let binary = assemble(".data 0x10\nmsg 'hi' _\n.entry main\n.code\nmain:\nhalt\n");
+    assert_eq!(
+        ImageFormat::IntelHex.write(&binary),
+        ":0A000000010210066869020000000A\n:00000001FF\n"
+    );
+    assert_eq!(
+        ImageFormat::Logisim.write(&binary),
+        "v2.0 raw\n01 02 10 06 68 69 02 00 00 00\n"
+    );
+    assert_eq!(
+        ImageFormat::HexLines
+            .write(&binary)
+            .lines()
+            .collect::<Vec<_>>(),
+        ["01", "02", "10", "06", "68", "69", "02", "00", "00", "00"]
+    );
+    assert_eq!(
+        ImageFormat::Verilog.write(&binary),
+        "@0000\n01 02 10 06 68 69 02 00 00 00\n"
+    );
+    assert_eq!(
+        ImageFormat::TuringComplete.write(&binary),
+        "0x01 0x02 0x10 0x06 0x68 0x69\n0x02 0x00 0x00 0x00\n"
+    );
+
+    // the commented binary, runs in Logisim images, and addresses in $readmemh
+    let commented = Assembler::new(include_str!("asm/hello_world.asm"))
+        .unwrap()
+        .assemble()
+        .unwrap();
+    assert_eq!(
+        image::load(commented.commented_binary.as_bytes()).unwrap(),
+        commented.binary.merge()
+    );
+    assert_eq!(
+        image::load(b"v2.0 raw\n01 3*0 # comment\n2*ff\n").unwrap(),
+        [1, 0, 0, 0, 0xff, 0xff]
+    );
+    assert_eq!(
+        image::load(b"01 02 // comment\n@5 03\n").unwrap(),
+        [1, 2, 0, 0, 0, 3]
+    );
+}
+
+#[test]
+fn errors() {
+    let error = |x: &[u8]| image::load(x).unwrap_err().to_string();
+    assert_eq!(
+        error(b":0A0000000102100668690200000068\n:00000001FF\n"),
+        "Intel HEX line 1: checksum mismatch"
+    );
+    assert_eq!(
+        error(b":0100000001FE\n"),
+        "Intel HEX: missing the end-of-file record"
+    );
+    assert_eq!(
+        error(b":020000040000FA\n:00000001FF\n"),
+        "Intel HEX line 1: unsupported record type 0x04"
+    );
+    assert_eq!(
+        error(b"v2.0 raw\n01 x*2\n"),
+        "Logisim image: invalid value `x*2`"
+    );
+    assert_eq!(error(b"01 0g\n"), "Invalid byte in the program: `0g`");
+    assert_eq!(
+        error(b"@10000 01\n"),
+        "`01` is beyond the 16-bit address space"
+    );
+    assert_eq!(error(b"\xff\xfe"), "Unknown program format");
+}
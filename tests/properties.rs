@@ -0,0 +1,340 @@
+//! Property tests: encoding round trips, and the emulator against a reference
+//! model of the ISA in `Manual.md`.
+
+use leg_cpu_emulator::assembler::Assembler;
+use leg_cpu_emulator::disassembler::disassemble;
+use leg_cpu_emulator::emulator::Emulator;
+use leg_cpu_emulator::instruction::{Opcode, Operand, OperandSymbol};
+use proptest::prelude::*;
+use proptest::sample::select;
+
+fn any_opcode() -> impl Strategy<Value = Opcode> {
+    let all = (0_u8..64)
+        .filter_map(|x| Opcode::try_from(x).ok())
+        .collect::<Vec<_>>();
+    select(all)
+}
+
+fn any_register() -> impl Strategy<Value = OperandSymbol> {
+    (0_u8..16).prop_map(|x| OperandSymbol::try_from(x).unwrap())
+}
+
+fn any_operand() -> impl Strategy<Value = Operand> {
+    prop_oneof![
+        any::<u8>().prop_map(Operand::Immediate),
+        any_register().prop_map(Operand::Symbol),
+    ]
+}
+
+/// Operands as the assembler would produce: the destination is a register,
+/// and `jamv`/`call` take the two address bytes.
+fn any_instruction() -> impl Strategy<Value = (Opcode, Vec<Operand>)> {
+    any_opcode().prop_flat_map(|opcode| {
+        let count = match opcode {
+            Opcode::JumpAddrMove | Opcode::Call => 2,
+            _ => opcode.asm_operand_count(),
+        };
+        let operands = (0..count)
+            .map(|i| {
+                let is_destination = opcode.destination_index().is_some() && i == count - 1;
+                match opcode {
+                    Opcode::JumpAddrMove | Opcode::Call => {
+                        any::<u8>().prop_map(Operand::Immediate).boxed()
+                    }
+                    _ if is_destination => any_register().prop_map(Operand::Symbol).boxed(),
+                    _ => any_operand().boxed(),
+                }
+            })
+            .collect::<Vec<_>>();
+        (Just(opcode), operands)
+    })
+}
+
+/// Assembly text of an instruction. `jamv` and `call` refer to `L<n>` labels.
+fn asm_statement(opcode: Opcode, operands: &[Operand], label_count: usize) -> String {
+    if opcode == Opcode::JumpAddrMove || opcode == Opcode::Call {
+        let target = operands[0].to_u8() as usize % label_count;
+        return format!("{opcode} L{target}");
+    }
+    let mut statement = opcode.to_string();
+    for (i, x) in operands.iter().enumerate() {
+        let is_destination = opcode.destination_index().is_some() && i == operands.len() - 1;
+        statement.push(' ');
+        match x {
+            Operand::Immediate(x) => statement.push_str(&x.to_string()),
+            Operand::Symbol(x) => statement.push_str(x.asm_name(is_destination)),
+        }
+    }
+    statement
+}
+
+proptest! {
+    #[test]
+    fn encode_decode_encode((opcode, operands) in any_instruction()) {
+        let inst = opcode.binary(&operands).unwrap();
+        prop_assert_eq!(Opcode::try_from(inst[0] & 0b00111111).unwrap(), opcode);
+        let decoded = opcode.decode_operands(&inst);
+        prop_assert_eq!(&decoded, &operands);
+        prop_assert_eq!(opcode.binary(&decoded).unwrap(), inst);
+    }
+
+    #[test]
+    fn assemble_disassemble_assemble(
+        program in prop::collection::vec(any_instruction(), 1..120),
+        data in prop::collection::vec(any::<u8>(), 0..40),
+        mem_start in 0_u8..100,
+    ) {
+        let mut source = format!(".data {mem_start}\n");
+        if !data.is_empty() {
+            let items = data.iter().map(|x| x.to_string()).collect::<Vec<_>>();
+            source.push_str(&format!("d [{}] _\n", items.join(", ")));
+        }
+        source.push_str(".entry L0\n.code\n");
+        for (i, (opcode, operands)) in program.iter().enumerate() {
+            source.push_str(&format!("L{i}:\n    {}\n", asm_statement(*opcode, operands, program.len())));
+        }
+
+        let binary = Assembler::new(&source).unwrap().assemble().unwrap().binary.merge();
+        let disassembly = disassemble(&binary).unwrap();
+        prop_assert!(disassembly.inexact.is_empty());
+        let reassembled = Assembler::new(&disassembly.code).unwrap().assemble().unwrap().binary.merge();
+        prop_assert_eq!(reassembled, binary);
+    }
+}
+
+/// The observable machine state.
+#[derive(Debug, Clone, PartialEq)]
+struct State {
+    pc: u16,
+    /// `r0`-`r11` and `fss`; the other slots are always 0.
+    registers: [u8; 16],
+    carry: bool,
+    jump_address: u16,
+    ram: Vec<u8>,
+    stack: Vec<u8>,
+    call_stack: Vec<u16>,
+    args_stack: Vec<u8>,
+    /// Remaining input, in order.
+    input: Vec<u8>,
+    output: Option<u8>,
+    halted: bool,
+}
+
+const PROGRAM_START: u16 = 4;
+
+fn any_state() -> impl Strategy<Value = State> {
+    (
+        prop::array::uniform16(any::<u8>()),
+        any::<bool>(),
+        any::<u16>(),
+        prop::collection::vec(any::<u8>(), 256),
+        prop::collection::vec(any::<u8>(), 0..4),
+        prop::collection::vec(any::<u16>(), 0..4),
+        prop::collection::vec(any::<u8>(), 0..4),
+        prop::collection::vec(any::<u8>(), 0..3),
+    )
+        .prop_map(
+            |(mut registers, carry, jump_address, ram, stack, call_stack, args_stack, input)| {
+                registers[12..15].fill(0);
+                State {
+                    pc: PROGRAM_START,
+                    registers,
+                    carry,
+                    jump_address,
+                    ram,
+                    stack,
+                    call_stack,
+                    args_stack,
+                    input,
+                    output: None,
+                    halted: false,
+                }
+            },
+        )
+}
+
+impl State {
+    fn read_register(&mut self, code: u8) -> u8 {
+        match code {
+            0..=11 | 15 => self.registers[code as usize],
+            12 if self.input.is_empty() => 0,
+            12 => self.input.remove(0),
+            13 => 1,
+            _ => 0,
+        }
+    }
+
+    fn write_register(&mut self, code: u8, value: u8) {
+        match code {
+            0..=11 | 15 => self.registers[code as usize] = value,
+            12 => self.output = Some(value),
+            _ => {}
+        }
+    }
+
+    /// Executes `inst` as described in the manual.
+    fn step(&mut self, inst: [u8; 4]) {
+        use Opcode::*;
+        let opcode = Opcode::try_from(inst[0] & 0b00111111).unwrap();
+        let source = |state: &mut Self, index: usize| {
+            let immediate = inst[0] & (0b10000000 >> (index - 1)) != 0;
+            if immediate {
+                inst[index]
+            } else {
+                state.read_register(inst[index])
+            }
+        };
+        let address = u16::from_le_bytes([inst[2], inst[3]]);
+        let mut next_pc = self.pc + 4;
+        self.output = None;
+
+        match opcode {
+            Add | Sub | And | Or | Not | Xor | MulLow | MulHigh | Shl | Shr | WShl | WShr | Div
+            | Mod | CAdd | Anc | Snc => {
+                let (a, b) = (source(self, 1), source(self, 2));
+                let wide = (a as u16, b as u16);
+                let result = match opcode {
+                    Add => (a.wrapping_add(b), Some(wide.0 + wide.1 > 255)),
+                    // carry is set when there's no borrow
+                    Sub => (a.wrapping_sub(b), Some(b != 0 && a >= b)),
+                    And => (a & b, Some(false)),
+                    Or => (a | b, Some(false)),
+                    Not => (!a, Some(false)),
+                    Xor => (a ^ b, Some(false)),
+                    MulLow => ((wide.0 * wide.1) as u8, Some(false)),
+                    MulHigh => (((wide.0 * wide.1) >> 8) as u8, Some(false)),
+                    Shl if b >= 8 => (0, None),
+                    Shl => (a << b, None),
+                    Shr if b >= 8 => (0, None),
+                    Shr => (a >> b, None),
+                    WShl => (a << (b % 8), None),
+                    WShr => (a >> (b % 8), None),
+                    Div => (a.checked_div(b).unwrap_or(0), None),
+                    Mod => (a.checked_rem(b).unwrap_or(0), None),
+                    CAdd => {
+                        let sum = wide.0 + wide.1 + self.carry as u16;
+                        (sum as u8, Some(sum > 255))
+                    }
+                    Anc => (a.wrapping_add(b), None),
+                    Snc => (a.wrapping_sub(b), None),
+                    _ => unreachable!(),
+                };
+                self.write_register(inst[3], result.0);
+                if let Some(x) = result.1 {
+                    self.carry = x;
+                }
+            }
+            JpEq | JpNe | JpLt | JpLe | JpGt | JpGe => {
+                let (a, b) = (source(self, 1), source(self, 2));
+                let condition = match opcode {
+                    JpEq => a == b,
+                    JpNe => a != b,
+                    JpLt => a < b,
+                    JpLe => a <= b,
+                    JpGt => a > b,
+                    _ => a >= b,
+                };
+                if condition {
+                    next_pc = self.jump_address;
+                }
+            }
+            Jp => next_pc = self.jump_address,
+            Load => {
+                let addr = source(self, 1);
+                self.write_register(inst[2], self.ram[addr as usize]);
+            }
+            Store => {
+                let (addr, value) = (source(self, 1), source(self, 2));
+                self.ram[addr as usize] = value;
+            }
+            Push => {
+                let value = source(self, 1);
+                self.stack.push(value);
+            }
+            Pop => {
+                let value = self.stack.pop().unwrap_or(0);
+                self.write_register(inst[1], value);
+            }
+            Call => {
+                self.call_stack.push(next_pc);
+                next_pc = address;
+            }
+            Return => next_pc = self.call_stack.pop().unwrap_or(0),
+            FPush => {
+                let value = source(self, 1);
+                self.args_stack.push(value);
+            }
+            FPop => {
+                let value = self.args_stack.pop().unwrap_or(0);
+                self.write_register(inst[1], value);
+            }
+            Mvc => self.write_register(inst[3], self.carry as u8),
+            Halt => self.halted = true,
+            Copy => {
+                let value = source(self, 1);
+                self.write_register(inst[3], value);
+            }
+            JumpAddrMove => self.jump_address = address,
+            Nop => {}
+        }
+        self.pc = next_pc;
+    }
+
+    fn to_emulator(&self, inst: [u8; 4]) -> Emulator {
+        let binary = [[1, 0, 0, PROGRAM_START as u8], inst].concat();
+        let mut emulator = Emulator::new(binary).unwrap();
+        emulator.set_input(self.input.clone());
+        emulator.ram.copy_from_slice(&self.ram);
+        emulator
+            .registers
+            .tier1_mut()
+            .copy_from_slice(&self.registers);
+        emulator.registers.set_carry(self.carry);
+        emulator.registers.set_jump_address(self.jump_address);
+        for &x in &self.stack {
+            emulator.stack.push(x).unwrap();
+        }
+        for &x in &self.call_stack {
+            emulator.f_call_stack.push(x).unwrap();
+        }
+        for &x in &self.args_stack {
+            emulator.f_args_stack.push(x).unwrap();
+        }
+        emulator
+    }
+
+    fn of_emulator(emulator: &Emulator) -> Self {
+        let input = emulator.input.queued().iter().copied().collect();
+        Self {
+            pc: *emulator.pc,
+            registers: emulator.registers.tier1().try_into().unwrap(),
+            carry: emulator.registers.carry(),
+            jump_address: emulator.registers.jump_address(),
+            ram: emulator.ram.clone(),
+            stack: emulator.stack.iter().copied().collect(),
+            call_stack: emulator.f_call_stack.iter().copied().collect(),
+            args_stack: emulator.f_args_stack.iter().copied().collect(),
+            input,
+            output: emulator.last_output,
+            halted: emulator.halted,
+        }
+    }
+}
+
+proptest! {
+    #![proptest_config(ProptestConfig::with_cases(2000))]
+
+    #[test]
+    fn tick_matches_reference_model(
+        (opcode, operands) in any_instruction(),
+        state in any_state(),
+    ) {
+        let inst = opcode.binary(&operands).unwrap();
+        let mut emulator = state.to_emulator(inst);
+        emulator.tick().unwrap();
+
+        let mut expected = state;
+        expected.step(inst);
+        prop_assert_eq!(State::of_emulator(&emulator), expected, "{} {:?}", opcode, inst);
+    }
+}
@@ -29,7 +29,7 @@ impl LegCpu {
     pub fn assemble(code: &str) -> crate::Result<LegAssemblyTarget> {
         let result: anyhow::Result<_> = try {
             let assembler = leg::assembler::Assembler::new(code)?;
-            let target = assembler.assemble();
+            let target = assembler.assemble()?;
             LegAssemblyTarget {
                 binary: {
                     let mut joined = target.binary.header;
@@ -43,37 +43,21 @@ impl LegCpu {
     }
 
     pub fn emulate(binary: &[u8], input: &str, cycles_limit: Option<u64>) -> crate::Result<LegEmulationResult> {
-        let cycles_limit = cycles_limit.unwrap_or(u64::MAX);
         let result: anyhow::Result<_> = try {
             let mut emulator = leg::emulator::Emulator::new(binary)?;
             // always append a new line
             let mut input = input.as_bytes().to_vec();
             input.push(b'\n');
             emulator.set_input(input);
-            let mut cycle = 0_u64;
-            let mut output = Vec::new();
-            let mut interrupted = false;
-            loop {
-                emulator.tick()?;
-                cycle += 1;
-                if emulator.halted {
-                    break;
-                }
-                if cycle > cycles_limit {
-                    interrupted = true;
-                    break;
-                }
-                if let Some(o) = &emulator.output {
-                    output.push(**o);
-                }
-            }
+            let stop_reason = emulator.run(cycles_limit)?;
+            let output = emulator.take_output();
             let output_lossy_string = String::from_utf8_lossy(&output).to_string();
             let ram_pretty_hex = pretty_hex::pretty_hex(&emulator.ram);
             LegEmulationResult {
                 output_lossy_string,
                 output,
-                cpu_cycles: cycle,
-                interrupted,
+                cpu_cycles: emulator.cycles,
+                interrupted: stop_reason == leg::emulator::StopReason::BudgetExhausted,
                 ram: emulator.ram,
                 ram_pretty_hex,
             }